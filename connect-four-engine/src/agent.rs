@@ -0,0 +1,733 @@
+use crate::bitboard::*;
+use crate::book::OpeningBook;
+use crate::bot::{SearchHandle, SearchLimits, SearchResult, search_with_handle};
+use crate::error::EngineError;
+use crate::eval::{EvalParams, Evaluator};
+use crate::mcts::{MctsParams, mcts_with_handle};
+use crate::network::{DEFAULT_HIDDEN, Network};
+use crate::ntuple::{DEFAULT_TUPLE_LENGTH, DEFAULT_TUPLES, NTupleNetwork};
+use crate::puct::{PuctParams, puct_with_handle};
+use crate::rng::Rng;
+use crate::solver::Solver;
+use crate::transposition::TranspositionTable;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// A bot that chooses moves. Front-ends and self-play hold agents as
+/// `Box<dyn Agent>`, so any agent can face any other.
+pub trait Agent: Send {
+    /// Short description for menus and match reports.
+    fn name(&self) -> String;
+
+    /// Picks a column for the side to move. Agents that search return
+    /// early once `handle` is stopped, and may report each completed
+    /// iteration to `on_progress`.
+    fn choose_move_with_handle(
+        &mut self,
+        position: &Position,
+        handle: &SearchHandle,
+        on_progress: &mut dyn FnMut(&SearchResult),
+    ) -> Result<usize, EngineError>;
+
+    fn choose_move(&mut self, position: &Position) -> Result<usize, EngineError> {
+        self.choose_move_with_handle(position, &SearchHandle::new(), &mut |_| {})
+    }
+
+    /// Forgets anything kept from the previous game.
+    fn new_game(&mut self) {}
+}
+
+/// Plays any legal column with equal chance.
+#[derive(Default)]
+pub struct RandomAgent {
+    rng: Rng,
+}
+
+impl RandomAgent {
+    pub fn new(rng: Rng) -> RandomAgent {
+        RandomAgent { rng }
+    }
+}
+
+impl Agent for RandomAgent {
+    fn name(&self) -> String {
+        "random".to_string()
+    }
+
+    fn choose_move_with_handle(
+        &mut self,
+        position: &Position,
+        _handle: &SearchHandle,
+        _on_progress: &mut dyn FnMut(&SearchResult),
+    ) -> Result<usize, EngineError> {
+        if position.is_game_over() {
+            return Err(EngineError::GameOver);
+        }
+        let size = position.size();
+        let columns = size.columns(size.playable(position.mask()));
+        Ok(self.rng.choose(&columns).unwrap())
+    }
+}
+
+/// Looks one move ahead: wins when it can, avoids handing the opponent a
+/// win, and otherwise plays the move that evaluates best.
+pub struct GreedyAgent {
+    evaluator: Box<dyn Evaluator>,
+}
+
+impl GreedyAgent {
+    pub fn new(evaluator: impl Evaluator + 'static) -> GreedyAgent {
+        GreedyAgent {
+            evaluator: Box::new(evaluator),
+        }
+    }
+}
+
+impl Default for GreedyAgent {
+    fn default() -> GreedyAgent {
+        GreedyAgent::new(EvalParams::default())
+    }
+}
+
+impl Agent for GreedyAgent {
+    fn name(&self) -> String {
+        "greedy".to_string()
+    }
+
+    fn choose_move_with_handle(
+        &mut self,
+        position: &Position,
+        _handle: &SearchHandle,
+        _on_progress: &mut dyn FnMut(&SearchResult),
+    ) -> Result<usize, EngineError> {
+        if position.is_game_over() {
+            return Err(EngineError::GameOver);
+        }
+        let size = position.size();
+        if let Some(&col) = size.columns(position.winning_moves()).first() {
+            return Ok(col);
+        }
+        let mut candidates = position.non_losing_moves();
+        if candidates == 0 {
+            candidates = size.playable(position.mask());
+        }
+
+        let mut child = position.clone();
+        let mut best = None;
+        for col in size.centre_out() {
+            if candidates & size.col_mask(col) == 0 {
+                continue;
+            }
+            child.play_unchecked(col);
+            let score = -self.evaluator.evaluate(&child);
+            child.undo();
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((col, score));
+            }
+        }
+        Ok(best.unwrap().0)
+    }
+}
+
+/// The iterative deepening alpha-beta search of `bot`. Its table is kept
+/// for the whole game, so later moves reuse earlier work.
+pub struct MinimaxAgent {
+    limits: SearchLimits,
+    evaluator: Box<dyn Evaluator>,
+    table: TranspositionTable,
+}
+
+impl MinimaxAgent {
+    pub fn new(limits: SearchLimits, evaluator: impl Evaluator + 'static) -> MinimaxAgent {
+        MinimaxAgent {
+            limits,
+            evaluator: Box::new(evaluator),
+            table: TranspositionTable::default(),
+        }
+    }
+}
+
+impl Default for MinimaxAgent {
+    fn default() -> MinimaxAgent {
+        MinimaxAgent::new(SearchLimits::depth(8), EvalParams::default())
+    }
+}
+
+impl Agent for MinimaxAgent {
+    fn name(&self) -> String {
+        let mut name = "minimax".to_string();
+        if let Some(depth) = self.limits.depth {
+            name += &format!(" depth {}", depth);
+        }
+        if let Some(time) = self.limits.time {
+            name += &format!(" {}s", time.as_secs_f64());
+        }
+        if let Some(nodes) = self.limits.nodes {
+            name += &format!(" {} nodes", nodes);
+        }
+        name
+    }
+
+    fn choose_move_with_handle(
+        &mut self,
+        position: &Position,
+        handle: &SearchHandle,
+        on_progress: &mut dyn FnMut(&SearchResult),
+    ) -> Result<usize, EngineError> {
+        search_with_handle(
+            position,
+            self.limits,
+            self.evaluator.as_ref(),
+            &self.table,
+            handle,
+            on_progress,
+        )
+        .map(|result| result.best_move)
+    }
+
+    fn new_game(&mut self) {
+        self.table.clear();
+    }
+}
+
+/// Stones on the board before plain `solver` starts solving.
+pub const DEFAULT_SOLVE_FROM: usize = 12;
+/// Longest plain `solver` solves a move for before its opening agent moves
+/// instead.
+pub const DEFAULT_SOLVE_TIME: Duration = Duration::from_secs(10);
+
+/// Plays perfectly with `solver::Solver`. Solving the first moves of a
+/// game can take hours, so an opening agent may play until enough stones
+/// are on the board.
+pub struct SolverAgent {
+    solver: Solver,
+    opening: Option<(Box<dyn Agent>, usize)>,
+    time: Option<Duration>,
+}
+
+impl SolverAgent {
+    /// Solves every move for as long as it takes. Stopping the handle is
+    /// the only way to cut a solve short, and the column then returned is
+    /// just the most central legal one.
+    pub fn new() -> SolverAgent {
+        SolverAgent {
+            solver: Solver::default(),
+            opening: None,
+            time: None,
+        }
+    }
+
+    /// Lets `opening` move until `solve_from` stones are on the board, and
+    /// whenever a solve is stopped or takes longer than `time`.
+    pub fn with_opening(
+        opening: Box<dyn Agent>,
+        solve_from: usize,
+        time: Option<Duration>,
+    ) -> SolverAgent {
+        SolverAgent {
+            solver: Solver::default(),
+            opening: Some((opening, solve_from)),
+            time,
+        }
+    }
+}
+
+impl Default for SolverAgent {
+    fn default() -> SolverAgent {
+        SolverAgent::new()
+    }
+}
+
+impl Agent for SolverAgent {
+    fn name(&self) -> String {
+        match &self.opening {
+            Some((opening, solve_from)) => {
+                format!("solver from move {} after {}", solve_from, opening.name())
+            }
+            None => "solver".to_string(),
+        }
+    }
+
+    fn choose_move_with_handle(
+        &mut self,
+        position: &Position,
+        handle: &SearchHandle,
+        on_progress: &mut dyn FnMut(&SearchResult),
+    ) -> Result<usize, EngineError> {
+        if let Some((opening, solve_from)) = &mut self.opening
+            && position.moves_played() < *solve_from
+        {
+            return opening.choose_move_with_handle(position, handle, on_progress);
+        }
+        let solved = self
+            .solver
+            .best_move_with_handle(position, self.time, handle)?;
+        match (solved, &mut self.opening) {
+            (Some(col), _) => Ok(col),
+            (None, Some((opening, _))) => {
+                opening.choose_move_with_handle(position, handle, on_progress)
+            }
+            (None, None) => Ok(position
+                .size()
+                .centre_out()
+                .find(|&col| position.can_play(col))
+                .expect("a game in progress has a legal move")),
+        }
+    }
+
+    fn new_game(&mut self) {
+        self.solver.reset();
+        if let Some((opening, _)) = &mut self.opening {
+            opening.new_game();
+        }
+    }
+}
+
+/// Monte Carlo tree search with `mcts`. At small budgets it misses deeper
+/// tactics the way a beginner would, rather than playing perfectly up to
+/// a horizon.
+pub struct MctsAgent {
+    params: MctsParams,
+    rng: Rng,
+}
+
+impl MctsAgent {
+    pub fn new(params: MctsParams, rng: Rng) -> MctsAgent {
+        MctsAgent { params, rng }
+    }
+}
+
+impl Default for MctsAgent {
+    fn default() -> MctsAgent {
+        MctsAgent::new(MctsParams::default(), Rng::default())
+    }
+}
+
+impl Agent for MctsAgent {
+    fn name(&self) -> String {
+        let mut name = "mcts".to_string();
+        if let Some(iterations) = self.params.iterations {
+            name += &format!(" {} playouts", iterations);
+        }
+        if let Some(time) = self.params.time {
+            name += &format!(" {}s", time.as_secs_f64());
+        }
+        name
+    }
+
+    fn choose_move_with_handle(
+        &mut self,
+        position: &Position,
+        handle: &SearchHandle,
+        _on_progress: &mut dyn FnMut(&SearchResult),
+    ) -> Result<usize, EngineError> {
+        mcts_with_handle(position, &self.params, &mut self.rng, handle)
+            .map(|result| result.best_move)
+    }
+}
+
+/// Searches with `puct`, guided by a policy/value network.
+pub struct PuctAgent {
+    network: Network,
+    params: PuctParams,
+}
+
+impl PuctAgent {
+    pub fn new(network: Network, params: PuctParams) -> PuctAgent {
+        PuctAgent { network, params }
+    }
+}
+
+impl Agent for PuctAgent {
+    fn name(&self) -> String {
+        format!("puct {} simulations", self.params.simulations)
+    }
+
+    fn choose_move_with_handle(
+        &mut self,
+        position: &Position,
+        handle: &SearchHandle,
+        _on_progress: &mut dyn FnMut(&SearchResult),
+    ) -> Result<usize, EngineError> {
+        puct_with_handle(position, &self.network, &self.params, handle)
+            .map(|result| result.best_move)
+    }
+}
+
+/// Plays a random recommended move while the position is in `book`, so
+/// games between bots start differently, and leaves the rest of the game
+/// to `agent`.
+pub struct BookAgent {
+    book: OpeningBook,
+    agent: Box<dyn Agent>,
+    rng: Rng,
+}
+
+impl BookAgent {
+    pub fn new(book: OpeningBook, agent: Box<dyn Agent>, rng: Rng) -> BookAgent {
+        BookAgent { book, agent, rng }
+    }
+}
+
+impl Agent for BookAgent {
+    fn name(&self) -> String {
+        format!("{} with book", self.agent.name())
+    }
+
+    fn choose_move_with_handle(
+        &mut self,
+        position: &Position,
+        handle: &SearchHandle,
+        on_progress: &mut dyn FnMut(&SearchResult),
+    ) -> Result<usize, EngineError> {
+        match self.book.choose(position, &mut self.rng) {
+            Some(col) => Ok(col),
+            None => self
+                .agent
+                .choose_move_with_handle(position, handle, on_progress),
+        }
+    }
+
+    fn new_game(&mut self) {
+        self.agent.new_game();
+    }
+}
+
+/// Agent names understood by `create_agent`.
+pub const AGENT_NAMES: [&str; 8] = [
+    "random", "greedy", "minimax", "ntuple", "mcts", "puct", "solver", "book",
+];
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum AgentError {
+    UnknownAgent { name: String },
+    InvalidArgument { name: String, argument: String },
+    InvalidFile { path: String, message: String },
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AgentError::UnknownAgent { name } => write!(
+                f,
+                "unknown agent '{}', expected one of {}",
+                name,
+                AGENT_NAMES.join(", ")
+            ),
+            AgentError::InvalidArgument { name, argument } => {
+                write!(f, "invalid argument '{}' for agent '{}'", argument, name)
+            }
+            AgentError::InvalidFile { path, message } => write!(f, "{}: {}", path, message),
+        }
+    }
+}
+
+impl Error for AgentError {}
+
+/// Builds an agent from a name, optionally followed by a colon and an
+/// argument:
+///
+/// - `random`, `greedy`
+/// - `minimax` searches 8 plies; `minimax:12` sets the depth and
+///   `minimax:1.5s` a time per move instead; `minimax:10:FILE` also
+///   evaluates with the parameters or n-tuple network saved in `FILE`
+/// - `ntuple:FILE` is the default minimax evaluating with the n-tuple
+///   network saved in `FILE`; plain `ntuple` uses an untrained one
+/// - `mcts` runs 10000 playouts; `mcts:500` sets the playouts and
+///   `mcts:2s` a time per move instead
+/// - `puct:FILE` searches with the network saved in `FILE`; plain `puct`
+///   uses an untrained network as a baseline
+/// - `solver` lets the default minimax play until 12 stones are on the
+///   board, and whenever solving a move takes over 10 s; `solver:20` sets
+///   the stones
+/// - `book:FILE` plays from the opening book saved in `FILE`, then as the
+///   default minimax; `book:FILE:AGENT` hands over to `AGENT` instead
+pub fn create_agent(spec: &str) -> Result<Box<dyn Agent>, AgentError> {
+    let (name, argument) = match spec.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (spec, None),
+    };
+    let invalid = |argument: &str| AgentError::InvalidArgument {
+        name: name.to_string(),
+        argument: argument.to_string(),
+    };
+
+    match (name, argument) {
+        ("random", None) => Ok(Box::new(RandomAgent::default())),
+        ("greedy", None) => Ok(Box::new(GreedyAgent::default())),
+        ("minimax", None) => Ok(Box::new(MinimaxAgent::default())),
+        ("minimax", Some(argument)) => {
+            let (argument, file) = match argument.split_once(':') {
+                Some((argument, file)) => (argument, Some(file)),
+                None => (argument, None),
+            };
+            let limits = match argument.strip_suffix('s') {
+                Some(seconds) => seconds
+                    .parse()
+                    .ok()
+                    .filter(|&seconds: &f64| seconds.is_finite() && seconds > 0.0)
+                    .map(|seconds| SearchLimits::time(Duration::from_secs_f64(seconds))),
+                None => argument
+                    .parse()
+                    .ok()
+                    .filter(|&depth| depth > 0)
+                    .map(SearchLimits::depth),
+            }
+            .ok_or_else(|| invalid(argument))?;
+            let Some(path) = file else {
+                return Ok(Box::new(MinimaxAgent::new(limits, EvalParams::default())));
+            };
+            let invalid_file = |message: String| AgentError::InvalidFile {
+                path: path.to_string(),
+                message,
+            };
+            // N-tuple networks are recognised by their header, and anything
+            // else is read as evaluation parameters.
+            let is_network = NTupleNetwork::is_network_file(path)
+                .map_err(|error| invalid_file(error.to_string()))?;
+            if is_network {
+                let network =
+                    NTupleNetwork::load(path).map_err(|error| invalid_file(error.to_string()))?;
+                Ok(Box::new(MinimaxAgent::new(
+                    limits,
+                    standard_ntuple(path, network)?,
+                )))
+            } else {
+                let params =
+                    EvalParams::load(path).map_err(|error| invalid_file(error.to_string()))?;
+                Ok(Box::new(MinimaxAgent::new(limits, params)))
+            }
+        }
+        ("ntuple", None) => {
+            let network = NTupleNetwork::new(
+                Rules::STANDARD,
+                DEFAULT_TUPLES,
+                DEFAULT_TUPLE_LENGTH,
+                &mut Rng::default(),
+            );
+            Ok(Box::new(MinimaxAgent::new(SearchLimits::depth(8), network)))
+        }
+        ("ntuple", Some(path)) => {
+            let network = NTupleNetwork::load(path).map_err(|error| AgentError::InvalidFile {
+                path: path.to_string(),
+                message: error.to_string(),
+            })?;
+            Ok(Box::new(MinimaxAgent::new(
+                SearchLimits::depth(8),
+                standard_ntuple(path, network)?,
+            )))
+        }
+        ("mcts", None) => Ok(Box::new(MctsAgent::default())),
+        ("mcts", Some(argument)) => {
+            let params = match argument.strip_suffix('s') {
+                Some(seconds) => seconds
+                    .parse()
+                    .ok()
+                    .filter(|&seconds: &f64| seconds.is_finite() && seconds > 0.0)
+                    .map(|seconds| MctsParams::time(Duration::from_secs_f64(seconds))),
+                None => argument
+                    .parse()
+                    .ok()
+                    .filter(|&iterations| iterations > 0)
+                    .map(MctsParams::iterations),
+            }
+            .ok_or_else(|| invalid(argument))?;
+            Ok(Box::new(MctsAgent::new(params, Rng::default())))
+        }
+        ("puct", None) => {
+            let network = Network::new(Rules::STANDARD, &DEFAULT_HIDDEN, &mut Rng::default());
+            Ok(Box::new(PuctAgent::new(network, PuctParams::default())))
+        }
+        ("puct", Some(path)) => {
+            let network = Network::load(path).map_err(|error| AgentError::InvalidFile {
+                path: path.to_string(),
+                message: error.to_string(),
+            })?;
+            Ok(Box::new(PuctAgent::new(network, PuctParams::default())))
+        }
+        ("solver", argument) => {
+            let solve_from = match argument {
+                Some(argument) => argument.parse().map_err(|_| invalid(argument))?,
+                None => DEFAULT_SOLVE_FROM,
+            };
+            Ok(Box::new(SolverAgent::with_opening(
+                Box::new(MinimaxAgent::default()),
+                solve_from,
+                Some(DEFAULT_SOLVE_TIME),
+            )))
+        }
+        ("book", Some(argument)) => {
+            let (path, agent) = argument.split_once(':').unwrap_or((argument, "minimax"));
+            let book = OpeningBook::load(Rules::STANDARD, path).map_err(|error| {
+                AgentError::InvalidFile {
+                    path: path.to_string(),
+                    message: error.to_string(),
+                }
+            })?;
+            Ok(Box::new(BookAgent::new(
+                book,
+                create_agent(agent)?,
+                Rng::default(),
+            )))
+        }
+        (_, Some(argument)) if AGENT_NAMES.contains(&name) => Err(invalid(argument)),
+        ("book", None) => Err(invalid("")),
+        _ => Err(AgentError::UnknownAgent {
+            name: name.to_string(),
+        }),
+    }
+}
+
+/// Agents play the standard board, so networks trained for another one
+/// are rejected rather than fed positions they cannot read.
+fn standard_ntuple(path: &str, network: NTupleNetwork) -> Result<NTupleNetwork, AgentError> {
+    if network.rules() != Rules::STANDARD {
+        return Err(AgentError::InvalidFile {
+            path: path.to_string(),
+            message: "the network was trained under different rules".to_string(),
+        });
+    }
+    Ok(network)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+
+    #[test]
+    fn test_agents_play_legal_moves() {
+        let position = parse_moves(Rules::STANDARD, "4453").unwrap();
+        for name in [
+            "random",
+            "greedy",
+            "minimax:4",
+            "ntuple",
+            "mcts:200",
+            "puct",
+            "solver:20",
+        ] {
+            let mut agent = create_agent(name).unwrap();
+            let col = agent.choose_move(&position).unwrap();
+            assert!(position.can_play(col), "{}", name);
+        }
+
+        let over = parse_moves(Rules::STANDARD, "1122334").unwrap();
+        // A book agent needs a book file.
+        for name in AGENT_NAMES.into_iter().filter(|&name| name != "book") {
+            let mut agent = create_agent(name).unwrap();
+            assert_eq!(agent.choose_move(&over), Err(EngineError::GameOver));
+        }
+    }
+
+    #[test]
+    fn test_book_agent() {
+        let book = OpeningBook::parse(Rules::STANDARD, "- 1\n1 7").unwrap();
+        let mut agent = BookAgent::new(book, Box::new(MinimaxAgent::default()), Rng::new(1));
+        assert_eq!(agent.name(), "minimax depth 8 with book");
+        let mut position = Position::new();
+        for expected in [0, 6] {
+            assert_eq!(agent.choose_move(&position), Ok(expected));
+            position.play(expected).unwrap();
+        }
+        // Out of book, the minimax blocks First's bottom-row threat.
+        let position = parse_moves(Rules::STANDARD, "17273").unwrap();
+        assert_eq!(agent.choose_move(&position), Ok(3));
+    }
+
+    #[test]
+    fn test_greedy_agent() {
+        let mut agent = GreedyAgent::default();
+        // First wins in column 1 rather than blocking column 5.
+        let position = parse_moves(Rules::STANDARD, "121314").unwrap();
+        assert_eq!(agent.choose_move(&position), Ok(0));
+        // Second has no win, so it blocks First's three in column 1.
+        let position = parse_moves(Rules::STANDARD, "12131").unwrap();
+        assert_eq!(agent.choose_move(&position), Ok(0));
+    }
+
+    #[test]
+    fn test_solver_agent_stops() {
+        // Solving this takes far longer than any test should.
+        let position = parse_moves(Rules::STANDARD, "4444").unwrap();
+        let handle = SearchHandle::new();
+        handle.stop();
+        let mut agent = SolverAgent::new();
+        assert_eq!(
+            agent.choose_move_with_handle(&position, &handle, &mut |_| {}),
+            Ok(3)
+        );
+        let mut agent =
+            SolverAgent::with_opening(Box::new(GreedyAgent::default()), 0, Some(Duration::ZERO));
+        let col = agent.choose_move(&position).unwrap();
+        assert!(position.can_play(col));
+    }
+
+    #[test]
+    fn test_random_agent_is_seeded() {
+        let position = Position::new();
+        let mut a = RandomAgent::new(Rng::new(3));
+        let mut b = RandomAgent::new(Rng::new(3));
+        for _ in 0..20 {
+            assert_eq!(a.choose_move(&position), b.choose_move(&position));
+        }
+    }
+
+    #[test]
+    fn test_create_agent() {
+        assert_eq!(create_agent("minimax:6").unwrap().name(), "minimax depth 6");
+        assert_eq!(create_agent("minimax:0.5s").unwrap().name(), "minimax 0.5s");
+        assert_eq!(
+            create_agent("mcts:500").unwrap().name(),
+            "mcts 500 playouts"
+        );
+        assert_eq!(
+            create_agent("solver").unwrap().name(),
+            "solver from move 12 after minimax depth 8"
+        );
+        assert!(matches!(
+            create_agent("alphabeta"),
+            Err(AgentError::UnknownAgent { .. })
+        ));
+        assert!(matches!(
+            create_agent("minimax:deep"),
+            Err(AgentError::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            create_agent("puct:/nonexistent.net"),
+            Err(AgentError::InvalidFile { .. })
+        ));
+        assert!(matches!(
+            create_agent("minimax:6:/nonexistent.txt"),
+            Err(AgentError::InvalidFile { .. })
+        ));
+
+        let path = std::env::temp_dir().join(format!("agent-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "connect_three = 12\n").unwrap();
+        let agent = create_agent(&format!("minimax:4:{}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(agent.unwrap().name(), "minimax depth 4");
+
+        // A truncated network is reported as one, not parsed as text.
+        let path = std::env::temp_dir().join(format!("agent-test-{}.c4nt", std::process::id()));
+        std::fs::write(&path, b"C4NT\x01").unwrap();
+        let agent = create_agent(&format!("minimax:4:{}", path.display()));
+        let Err(AgentError::InvalidFile { message, .. }) = agent else {
+            panic!("truncated network accepted");
+        };
+        assert!(!message.starts_with("line"), "{}", message);
+
+        let rules = Rules::new(BoardSize::new(6, 5).unwrap(), 4).unwrap();
+        NTupleNetwork::new(rules, 4, 4, &mut Rng::new(1))
+            .save(&path)
+            .unwrap();
+        let agent = create_agent(&format!("ntuple:{}", path.display()));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(agent, Err(AgentError::InvalidFile { .. })));
+        assert!(matches!(
+            create_agent("random:3"),
+            Err(AgentError::InvalidArgument { .. })
+        ));
+    }
+}
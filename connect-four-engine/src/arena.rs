@@ -0,0 +1,421 @@
+use crate::agent::{Agent, AgentError, create_agent};
+use crate::bitboard::*;
+use crate::book::{BOOK_DEPTH, BOOK_WINDOW, balanced_openings, openings};
+use rayon::prelude::*;
+use std::error::Error;
+use std::fmt;
+
+/// Which pairs of agents play each other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Schedule {
+    /// Every agent against every other.
+    RoundRobin,
+    /// The first agent against each of the others.
+    Gauntlet,
+}
+
+/// A sequential probability ratio test between two hypotheses about the
+/// Elo difference of a pair, stopping the match as soon as the games
+/// favour one of them.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SprtParams {
+    /// Elo difference under the null hypothesis.
+    pub elo0: f64,
+    /// Elo difference under the alternative hypothesis.
+    pub elo1: f64,
+    /// Chance of accepting `elo1` when `elo0` is true.
+    pub alpha: f64,
+    /// Chance of accepting `elo0` when `elo1` is true.
+    pub beta: f64,
+}
+
+impl SprtParams {
+    /// Tests `elo0` against `elo1` with 5% error rates both ways.
+    pub fn new(elo0: f64, elo1: f64) -> SprtParams {
+        SprtParams {
+            elo0,
+            elo1,
+            alpha: 0.05,
+            beta: 0.05,
+        }
+    }
+
+    /// Decides on the games played so far.
+    pub fn test(&self, result: &PairResult) -> SprtResult {
+        let llr = result.llr(self);
+        if llr >= ((1.0 - self.beta) / self.alpha).ln() {
+            SprtResult::AcceptH1
+        } else if llr <= (self.beta / (1.0 - self.alpha)).ln() {
+            SprtResult::AcceptH0
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SprtResult {
+    /// Neither hypothesis is accepted yet.
+    Continue,
+    /// The first agent is no more than `elo0` stronger.
+    AcceptH0,
+    /// The first agent is at least `elo1` stronger.
+    AcceptH1,
+}
+
+/// Settings of `run_arena`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ArenaParams {
+    pub schedule: Schedule,
+    /// Games per pair, or the most games per pair under an SPRT.
+    pub games: usize,
+    /// Moves in each opening. Every opening is played twice per pair, once
+    /// with each agent moving first.
+    pub opening_plies: usize,
+    /// When set, only openings that search to within this score of even at
+    /// `BOOK_DEPTH` are played; otherwise every opening is.
+    pub opening_window: Option<f32>,
+    pub sprt: Option<SprtParams>,
+}
+
+impl Default for ArenaParams {
+    fn default() -> ArenaParams {
+        ArenaParams {
+            schedule: Schedule::RoundRobin,
+            games: 98,
+            opening_plies: 2,
+            opening_window: Some(BOOK_WINDOW),
+            sprt: None,
+        }
+    }
+}
+
+/// The games between two agents, counted from the first agent's side.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PairResult {
+    pub first: String,
+    pub second: String,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    /// The final decision when the match ran an SPRT.
+    pub sprt: Option<SprtResult>,
+}
+
+impl PairResult {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    /// The first agent's mean score, counting a draw as half a win.
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    /// How many Elo points stronger the first agent performed. Infinite
+    /// when one side won every game.
+    pub fn elo(&self) -> f64 {
+        elo(self.score())
+    }
+
+    /// Half the width of the 95% confidence interval of `elo`, infinite
+    /// while one side has won every game.
+    pub fn elo_error(&self) -> f64 {
+        let score = self.score();
+        if score == 0.0 || score == 1.0 {
+            return f64::INFINITY;
+        }
+        let margin = 1.96 * (self.variance() / self.games().max(1) as f64).sqrt();
+        let low = elo((score - margin).max(0.0));
+        let high = elo((score + margin).min(1.0));
+        (high - low) / 2.0
+    }
+
+    /// Log-likelihood ratio of `sprt.elo1` against `sprt.elo0`, using the
+    /// normal approximation to the distribution of the mean score. While
+    /// every game has ended the same way the variance is taken as if one
+    /// more game had been drawn, since it would otherwise be zero.
+    pub fn llr(&self, sprt: &SprtParams) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let mut variance = self.variance();
+        if variance == 0.0 {
+            variance = PairResult {
+                draws: self.draws + 1,
+                ..self.clone()
+            }
+            .variance();
+        }
+        let (score0, score1) = (expected_score(sprt.elo0), expected_score(sprt.elo1));
+        self.games() as f64 * (score1 - score0) * (2.0 * self.score() - score0 - score1)
+            / (2.0 * variance)
+    }
+
+    /// Variance of the score of a single game.
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        let games = self.games().max(1) as f64;
+        (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / games
+    }
+}
+
+fn elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Plays matches between agents given as `create_agent` specs, cycling
+/// through `openings` and swapping colours after each game. Game pairs
+/// are played in parallel, so agents limited by time get less of the
+/// machine each than when playing alone. `on_pair` receives each pair's
+/// result as its match ends.
+pub fn run_arena(
+    specs: &[String],
+    params: &ArenaParams,
+    mut on_pair: impl FnMut(&PairResult),
+) -> Result<Vec<PairResult>, ArenaError> {
+    for spec in specs {
+        create_agent(spec)?;
+    }
+    let pairs: Vec<(usize, usize)> = match params.schedule {
+        Schedule::RoundRobin => (0..specs.len())
+            .flat_map(|i| (i + 1..specs.len()).map(move |j| (i, j)))
+            .collect(),
+        Schedule::Gauntlet => (1..specs.len()).map(|j| (0, j)).collect(),
+    };
+    let plies = params.opening_plies;
+    let openings = match params.opening_window {
+        Some(window) => balanced_openings(Rules::STANDARD, plies, BOOK_DEPTH, window),
+        None => openings(Rules::STANDARD, plies),
+    };
+    if openings.is_empty() {
+        return Err(ArenaError::NoOpenings {
+            plies,
+            window: params.opening_window,
+        });
+    }
+
+    let mut results = Vec::new();
+    for (i, j) in pairs {
+        let result = play_match(&specs[i], &specs[j], &openings, params);
+        on_pair(&result);
+        results.push(result);
+    }
+    Ok(results)
+}
+
+fn play_match(
+    first: &str,
+    second: &str,
+    openings: &[Position],
+    params: &ArenaParams,
+) -> PairResult {
+    let mut result = PairResult {
+        first: first.to_string(),
+        second: second.to_string(),
+        wins: 0,
+        draws: 0,
+        losses: 0,
+        sprt: params.sprt.map(|_| SprtResult::Continue),
+    };
+    // Games are played in pairs on the same opening, a batch of pairs at a
+    // time so the SPRT can stop the match between batches.
+    let mut played = 0;
+    while played < params.games && result.sprt.is_none_or(|sprt| sprt == SprtResult::Continue) {
+        let batch = (params.games - played).min(2 * rayon::current_num_threads());
+        let scores: Vec<f64> = (played..played + batch)
+            .collect::<Vec<_>>()
+            .par_chunks(2)
+            .flat_map_iter(|games| {
+                let opening = &openings[games[0] / 2 % openings.len()];
+                let mut a = create_agent(first).unwrap();
+                let mut b = create_agent(second).unwrap();
+                games
+                    .iter()
+                    .map(|game| {
+                        a.new_game();
+                        b.new_game();
+                        if game % 2 == 0 {
+                            play_game(opening, a.as_mut(), b.as_mut())
+                        } else {
+                            1.0 - play_game(opening, b.as_mut(), a.as_mut())
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        for score in scores {
+            if score == 1.0 {
+                result.wins += 1;
+            } else if score == 0.0 {
+                result.losses += 1;
+            } else {
+                result.draws += 1;
+            }
+        }
+        played += batch;
+        if let Some(sprt) = &params.sprt {
+            result.sprt = Some(sprt.test(&result));
+        }
+    }
+    result
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ArenaError {
+    Agent(AgentError),
+    /// No opening of `plies` moves is in progress and, when `window` is
+    /// set, within it of even.
+    NoOpenings {
+        plies: usize,
+        window: Option<f32>,
+    },
+}
+
+impl fmt::Display for ArenaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArenaError::Agent(error) => write!(f, "{}", error),
+            ArenaError::NoOpenings {
+                plies,
+                window: Some(window),
+            } => write!(
+                f,
+                "no opening of {} moves scores within {} of even",
+                plies, window
+            ),
+            ArenaError::NoOpenings {
+                plies,
+                window: None,
+            } => write!(f, "no opening of {} moves is still in progress", plies),
+        }
+    }
+}
+
+impl Error for ArenaError {}
+
+impl From<AgentError> for ArenaError {
+    fn from(error: AgentError) -> ArenaError {
+        ArenaError::Agent(error)
+    }
+}
+
+/// Plays out `opening` and returns the score of the agent moving first
+/// there. An agent that fails to move or picks an illegal column loses.
+fn play_game(opening: &Position, first: &mut dyn Agent, second: &mut dyn Agent) -> f64 {
+    let mut position = opening.clone();
+    let first_player = position.side_to_move();
+    while !position.is_game_over() {
+        let choice = if position.side_to_move() == first_player {
+            first.choose_move(&position)
+        } else {
+            second.choose_move(&position)
+        };
+        let played = choice.ok().and_then(|col| position.play(col).ok());
+        if played.is_none() {
+            return if position.side_to_move() == first_player {
+                0.0
+            } else {
+                1.0
+            };
+        }
+    }
+    match position.outcome() {
+        Outcome::Won(winner) if winner == first_player => 1.0,
+        Outcome::Won(_) => 0.0,
+        _ => 0.5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elo_and_sprt() {
+        let even = PairResult {
+            first: "a".to_string(),
+            second: "b".to_string(),
+            wins: 30,
+            draws: 40,
+            losses: 30,
+            sprt: None,
+        };
+        assert_eq!(even.elo(), 0.0);
+        assert!(even.elo_error() > 0.0);
+        let sprt = SprtParams::new(0.0, 50.0);
+        assert!(even.llr(&sprt) < 0.0);
+
+        let strong = PairResult {
+            wins: 150,
+            draws: 40,
+            losses: 10,
+            ..even.clone()
+        };
+        assert!((strong.elo() - 301.2).abs() < 1.0);
+        assert_eq!(sprt.test(&strong), SprtResult::AcceptH1);
+        assert_eq!(
+            sprt.test(&PairResult { wins: 0, ..strong }),
+            SprtResult::AcceptH0
+        );
+
+        let sweep = PairResult {
+            wins: 20,
+            draws: 0,
+            losses: 0,
+            ..even
+        };
+        assert_eq!(sweep.elo_error(), f64::INFINITY);
+        assert_eq!(sprt.test(&sweep), SprtResult::AcceptH1);
+    }
+
+    #[test]
+    fn test_run_arena() {
+        let specs = [
+            "random".to_string(),
+            "minimax:4".to_string(),
+            "greedy".to_string(),
+        ];
+        let params = ArenaParams {
+            schedule: Schedule::Gauntlet,
+            games: 10,
+            ..ArenaParams::default()
+        };
+        let mut reported = 0;
+        let results = run_arena(&specs, &params, |_| reported += 1).unwrap();
+        assert_eq!(reported, 2);
+        assert_eq!(results[0].games(), 10);
+        assert_eq!(
+            (results[0].first.as_str(), results[0].second.as_str()),
+            ("random", "minimax:4")
+        );
+        assert!(results[0].losses > results[0].wins);
+
+        assert!(matches!(
+            run_arena(
+                &["random".to_string(), "chess".to_string()],
+                &params,
+                |_| {}
+            ),
+            Err(ArenaError::Agent(AgentError::UnknownAgent { .. }))
+        ));
+        let params = ArenaParams {
+            opening_window: Some(-1.0),
+            ..params
+        };
+        assert_eq!(
+            run_arena(&specs, &params, |_| {}),
+            Err(ArenaError::NoOpenings {
+                plies: 2,
+                window: Some(-1.0)
+            })
+        );
+    }
+}
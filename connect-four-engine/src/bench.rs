@@ -0,0 +1,226 @@
+use crate::bitboard::*;
+use crate::bot::{SearchLimits, SearchStats, search};
+use crate::notation::{NotationError, parse_moves};
+use crate::solver::Solver;
+use crate::transposition::TranspositionTable;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub const BENCH_DEPTH: usize = 12;
+
+/// Positions searched by `bench_search`, from the opening to the late
+/// middlegame. None is decided within `BENCH_DEPTH` plies, so each is
+/// searched to full depth. Changing them makes results incomparable with
+/// earlier runs.
+pub const BENCH_POSITIONS: [&str; 8] = [
+    "",
+    "4453",
+    "3344256",
+    "4444445",
+    "24112522732561",
+    "562233345135662377",
+    "4557316676566715512161",
+    "34652523415451457522363423",
+];
+
+/// Endgames from Pascal Pons' public test sets, solved when no test set
+/// files are given.
+pub const BENCH_TEST_SET: &str = "\
+2252576253462244111563365343671351441 -1
+7422341735647741166133573473242566 1
+23163416124767223154467471272416755633 0
+";
+
+// Test sets use Pons' format: one position per line, as a move string
+// followed by its solved score from the side to move's point of view.
+
+/// A position with its known solved score.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TestCase {
+    pub moves: String,
+    pub position: Position,
+    pub score: i32,
+}
+
+#[derive(Debug)]
+pub enum TestSetError {
+    Io(io::Error),
+    MissingScore { line: usize },
+    InvalidScore { line: usize, found: String },
+    InvalidMoves { line: usize, error: NotationError },
+}
+
+impl fmt::Display for TestSetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestSetError::Io(error) => write!(f, "{}", error),
+            TestSetError::MissingScore { line } => write!(f, "line {}: missing score", line + 1),
+            TestSetError::InvalidScore { line, found } => {
+                write!(f, "line {}: '{}' is not a score", line + 1, found)
+            }
+            TestSetError::InvalidMoves { line, error } => write!(f, "line {}: {}", line + 1, error),
+        }
+    }
+}
+
+impl Error for TestSetError {}
+
+impl From<io::Error> for TestSetError {
+    fn from(error: io::Error) -> TestSetError {
+        TestSetError::Io(error)
+    }
+}
+
+/// Parses a standard board test set, skipping blank lines.
+pub fn parse_test_set(text: &str) -> Result<Vec<TestCase>, TestSetError> {
+    let mut cases = Vec::new();
+    for (line, content) in text.lines().enumerate() {
+        let mut fields = content.split_whitespace();
+        let Some(moves) = fields.next() else {
+            continue;
+        };
+        let found = fields.next().ok_or(TestSetError::MissingScore { line })?;
+        let score = found.parse().map_err(|_| TestSetError::InvalidScore {
+            line,
+            found: found.to_string(),
+        })?;
+        let position = parse_moves(Rules::STANDARD, moves)
+            .map_err(|error| TestSetError::InvalidMoves { line, error })?;
+        cases.push(TestCase {
+            moves: moves.to_string(),
+            position,
+            score,
+        });
+    }
+    Ok(cases)
+}
+
+pub fn read_test_set(path: impl AsRef<Path>) -> Result<Vec<TestCase>, TestSetError> {
+    parse_test_set(&fs::read_to_string(path)?)
+}
+
+/// Writes cases in the format `parse_test_set` reads.
+pub fn format_test_set(cases: &[TestCase]) -> String {
+    cases
+        .iter()
+        .map(|case| format!("{} {}\n", case.moves, case.score))
+        .collect()
+}
+
+/// Searches each of `positions` to `depth` with a fresh table.
+pub fn bench_search(positions: &[Position], depth: usize) -> Vec<SearchStats> {
+    positions
+        .iter()
+        .filter_map(|position| {
+            let table = TranspositionTable::default();
+            search(position, SearchLimits::depth(depth), &table).ok()
+        })
+        .map(|result| result.stats)
+        .collect()
+}
+
+/// Outcome of solving a test set.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SolverBench {
+    pub cases: usize,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    /// Cases solved to a different score, with the score found.
+    pub failures: Vec<(TestCase, i32)>,
+}
+
+impl SolverBench {
+    pub fn correct(&self) -> usize {
+        self.cases - self.failures.len()
+    }
+
+    pub fn mean_time(&self) -> Duration {
+        self.elapsed / self.cases.max(1) as u32
+    }
+
+    pub fn mean_nodes(&self) -> f64 {
+        self.nodes as f64 / self.cases.max(1) as f64
+    }
+
+    pub fn nodes_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.nodes as f64 / seconds
+    }
+}
+
+/// Solves every case from a cold table, as Pons' benchmarks do, and checks
+/// the score.
+pub fn bench_solver(cases: &[TestCase]) -> SolverBench {
+    let solver = Solver::default();
+    let mut bench = SolverBench::default();
+    for case in cases {
+        solver.reset();
+        let start = Instant::now();
+        let score = solver
+            .solve(&case.position)
+            .map_or(0, |solution| solution.score());
+        bench.elapsed += start.elapsed();
+        bench.nodes += solver.nodes();
+        bench.cases += 1;
+        if score != case.score {
+            bench.failures.push((case.clone(), score));
+        }
+    }
+    bench
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_positions_reach_full_depth() {
+        let positions: Vec<Position> = BENCH_POSITIONS
+            .iter()
+            .map(|moves| parse_moves(Rules::STANDARD, moves).unwrap())
+            .collect();
+        let stats = bench_search(&positions, BENCH_DEPTH);
+        assert_eq!(stats.len(), BENCH_POSITIONS.len());
+        for (moves, stats) in BENCH_POSITIONS.iter().zip(&stats) {
+            assert_eq!(stats.depth, BENCH_DEPTH, "{}", moves);
+        }
+    }
+
+    #[test]
+    fn test_parse_test_set() {
+        let cases = parse_test_set(BENCH_TEST_SET).unwrap();
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases[0].score, -1);
+        assert_eq!(cases[2].position.moves_played(), 38);
+        assert_eq!(format_test_set(&cases), BENCH_TEST_SET);
+
+        assert!(matches!(
+            parse_test_set("4453"),
+            Err(TestSetError::MissingScore { line: 0 })
+        ));
+        assert!(matches!(
+            parse_test_set("\n4453 x"),
+            Err(TestSetError::InvalidScore { line: 1, .. })
+        ));
+        assert!(matches!(
+            parse_test_set("48 0"),
+            Err(TestSetError::InvalidMoves { line: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_bench_solver() {
+        let cases = parse_test_set(BENCH_TEST_SET).unwrap();
+        let bench = bench_solver(&cases);
+        assert_eq!(bench.cases, 3);
+        assert_eq!(bench.correct(), 3);
+        assert!(bench.nodes > 0);
+    }
+}
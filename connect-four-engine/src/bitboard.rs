@@ -1,93 +1,605 @@
-pub type Bitboard = u64;
+use crate::error::EngineError;
+
+/// One bit per cell, column-major, with a spare sentinel bit above every
+/// column so shifts never wrap from one column into the next.
+pub type Bitboard = u128;
 
 pub const ROWS: usize = 6;
 pub const COLS: usize = 7;
+/// Widest supported board: move strings name columns with the 35 symbols
+/// 1-9 and a-z.
+pub const MAX_COLS: usize = 35;
+
+/// Width and height of a board. Any size up to `MAX_COLS` wide whose cells
+/// plus one sentinel row fit in a `Bitboard` is supported.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct BoardSize {
+    cols: usize,
+    rows: usize,
+}
+
+impl BoardSize {
+    pub const STANDARD: BoardSize = BoardSize {
+        cols: COLS,
+        rows: ROWS,
+    };
+
+    pub fn new(cols: usize, rows: usize) -> Option<BoardSize> {
+        if cols == 0 || rows == 0 || cols > MAX_COLS {
+            return None;
+        }
+        let bits = Bitboard::BITS as usize;
+        // Diagonal shifts are one more than the stride and must stay legal.
+        if cols * (rows + 1) > bits || rows + 2 >= bits {
+            return None;
+        }
+        Some(BoardSize { cols, rows })
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Number of bits between the bottoms of two neighbouring columns.
+    pub fn stride(&self) -> usize {
+        self.rows + 1
+    }
+
+    pub fn cells(&self) -> usize {
+        self.cols * self.rows
+    }
+
+    pub fn top_mask(&self, col: usize) -> Bitboard {
+        let shift: usize = col * self.stride() + self.rows - 1;
+        1 << shift
+    }
+
+    pub fn bottom_mask(&self, col: usize) -> Bitboard {
+        let shift: usize = col * self.stride();
+        1 << shift
+    }
+
+    pub fn col_mask(&self, col: usize) -> Bitboard {
+        let offset: usize = col * self.stride();
+        let bit: Bitboard = (1 << self.rows) - 1;
+        bit << offset
+    }
+
+    /// Bottom cell of every column.
+    pub fn bottom_row_mask(&self) -> Bitboard {
+        (0..self.cols).fold(0, |mask, col| mask | self.bottom_mask(col))
+    }
+
+    /// Every playable cell.
+    pub fn board_mask(&self) -> Bitboard {
+        self.bottom_row_mask() * ((1 << self.rows) - 1)
+    }
+
+    /// The lowest empty cell of every column that is not full.
+    pub fn playable(&self, mask: Bitboard) -> Bitboard {
+        (mask + self.bottom_row_mask()) & self.board_mask()
+    }
+
+    pub fn next_row(&self, bitboard: Bitboard, col: usize) -> Bitboard {
+        let next_row: Bitboard = bitboard + self.bottom_mask(col);
+        next_row & self.col_mask(col)
+    }
+
+    pub fn indices_from_bitmask(&self, bitboard: Bitboard) -> Option<(usize, usize)> {
+        let pos = bitboard.trailing_zeros() as usize;
+        let row = pos % self.stride();
+        let col = pos / self.stride();
+
+        if row < self.rows && col < self.cols {
+            return Some((row, col));
+        }
+        None
+    }
+
+    /// Row and column of every set cell, bottom-left first.
+    pub fn cells_from_bitmask(&self, bitboard: Bitboard) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        let mut remaining = bitboard & self.board_mask();
+        while remaining > 0 {
+            if let Some(cell) = self.indices_from_bitmask(remaining) {
+                cells.push(cell);
+            }
+            remaining &= remaining - 1;
+        }
+        cells
+    }
+
+    /// Columns with a set cell, left to right.
+    pub fn columns(&self, bitboard: Bitboard) -> Vec<usize> {
+        (0..self.cols)
+            .filter(|&col| bitboard & self.col_mask(col) > 0)
+            .collect()
+    }
+
+    pub fn can_place(&self, bitboard: Bitboard, col: usize) -> bool {
+        if col >= self.cols {
+            return false;
+        }
+        if (bitboard & self.top_mask(col)) > 0 {
+            return false;
+        }
+        true
+    }
+
+    pub fn is_board_full(&self, bitboard: Bitboard) -> bool {
+        bitboard.count_ones() as usize >= self.cells()
+    }
+
+    /// Columns from the centre outwards, left before right at each
+    /// distance. Even widths start at the right one of the two central
+    /// columns, giving 4, 3, 5, 2, ... on eight columns. Central columns
+    /// take part in the most lines, so they are usually best.
+    pub fn centre_out(&self) -> impl Iterator<Item = usize> {
+        let centre = self.cols / 2;
+        (0..self.cols).map(move |i| {
+            if i % 2 == 1 {
+                centre - i.div_ceil(2)
+            } else {
+                centre + i / 2
+            }
+        })
+    }
+
+    /// Shifts between neighbouring cells along the horizontal, vertical,
+    /// main diagonal and anti diagonal directions.
+    pub fn directions(&self) -> [usize; 4] {
+        let stride = self.stride();
+        [stride, 1, stride + 1, stride - 1]
+    }
+}
+
+impl Default for BoardSize {
+    fn default() -> BoardSize {
+        BoardSize::STANDARD
+    }
+}
+
+/// Board size and the length of the line needed to win.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Rules {
+    size: BoardSize,
+    connect: usize,
+}
+
+impl Rules {
+    pub const STANDARD: Rules = Rules {
+        size: BoardSize::STANDARD,
+        connect: 4,
+    };
+
+    pub fn new(size: BoardSize, connect: usize) -> Option<Rules> {
+        if connect < 2 || connect > size.cols().max(size.rows()) {
+            return None;
+        }
+        Some(Rules { size, connect })
+    }
+
+    pub fn size(&self) -> BoardSize {
+        self.size
+    }
+
+    pub fn connect(&self) -> usize {
+        self.connect
+    }
+
+    /// Cells that end a line of `length` stones in the given direction.
+    /// Doubles the covered length on every step so connect-N costs
+    /// O(log N) shifts.
+    pub fn lines(&self, bitboard: Bitboard, direction: usize, length: usize) -> Bitboard {
+        let mut line = bitboard;
+        let mut covered = 1;
+        while covered < length {
+            let step = covered.min(length - covered);
+            line &= line.checked_shl((direction * step) as u32).unwrap_or(0);
+            covered += step;
+        }
+        line
+    }
+
+    /// Cells that end a line of `length` stones in any direction.
+    pub fn all_lines(&self, bitboard: Bitboard, length: usize) -> Bitboard {
+        self.size.directions().iter().fold(0, |lines, &direction| {
+            lines | self.lines(bitboard, direction, length)
+        })
+    }
+
+    /// Cells that would complete a line for `bitboard`, whether or not they
+    /// are empty or reachable yet. A cell wins when the stones before and
+    /// after it along some direction add up to `connect - 1`.
+    pub fn winning_cells(&self, bitboard: Bitboard) -> Bitboard {
+        let mut cells: Bitboard = 0;
+        for direction in self.size.directions() {
+            // The last stone of every run of `before` stones, grown by one
+            // stone per step.
+            let mut run: Bitboard = !0;
+            for before in 0..self.connect {
+                let after = self.connect - 1 - before;
+                // Cells directly preceded by `before` stones and directly
+                // followed by `after` stones.
+                let preceded = match before {
+                    0 => !0,
+                    _ => run.checked_shl(direction as u32).unwrap_or(0),
+                };
+                let followed = match after {
+                    0 => !0,
+                    _ => self
+                        .lines(bitboard, direction, after)
+                        .checked_shr((direction * after) as u32)
+                        .unwrap_or(0),
+                };
+                cells |= preceded & followed;
+                run &= bitboard
+                    .checked_shl((direction * before) as u32)
+                    .unwrap_or(0);
+            }
+        }
+        cells & self.size.board_mask()
+    }
+
+    pub fn has_won(&self, bitboard: Bitboard) -> bool {
+        self.size
+            .directions()
+            .iter()
+            .any(|&direction| self.lines(bitboard, direction, self.connect) > 0)
+    }
+
+    /// Every stone that is part of a winning line. A single move can
+    /// complete several lines, or one longer than `connect`, so this may
+    /// hold more than `connect` cells.
+    pub fn winning_line(&self, bitboard: Bitboard) -> Bitboard {
+        let mut cells: Bitboard = 0;
+        for direction in self.size.directions() {
+            let ends = self.lines(bitboard, direction, self.connect);
+            for i in 0..self.connect {
+                cells |= ends.checked_shr((direction * i) as u32).unwrap_or(0);
+            }
+        }
+        cells
+    }
+
+    pub fn is_game_over(
+        &self,
+        game_board: Bitboard,
+        player1_board: Bitboard,
+        player2_board: Bitboard,
+    ) -> bool {
+        if self.has_won(player1_board) || self.has_won(player2_board) {
+            return true;
+        }
+        if self.size.is_board_full(game_board) {
+            return true;
+        }
+        false
+    }
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules::STANDARD
+    }
+}
+
+// Shorthands for standard connect four on a 7x6 board.
 
 pub fn get_top_mask(col: usize) -> Bitboard {
-    let shift: usize = col * COLS + ROWS - 1;
-    1 << shift
+    BoardSize::STANDARD.top_mask(col)
 }
 
 pub fn get_bottom_mask(col: usize) -> Bitboard {
-    let shift: usize = col * COLS;
-    1 << shift
+    BoardSize::STANDARD.bottom_mask(col)
 }
 
 pub fn get_col_mask(col: usize) -> Bitboard {
-    let offset: usize = col * COLS;
-    let bit: Bitboard = (1 << ROWS) - 1;
-    bit << offset
+    BoardSize::STANDARD.col_mask(col)
 }
 
 pub fn get_next_row(bitboard: Bitboard, col: usize) -> Bitboard {
-    let next_row: Bitboard = bitboard + get_bottom_mask(col);
-    next_row & get_col_mask(col)
+    BoardSize::STANDARD.next_row(bitboard, col)
 }
 
 pub fn indices_from_bitmask(bitboard: Bitboard) -> Option<(usize, usize)> {
-    let pos = bitboard.trailing_zeros() as usize;
-    let row = pos % (ROWS + 1);
-    let col = pos / (ROWS + 1);
-
-    if row < ROWS {
-        return Some((row, col));
-    }
-    None
+    BoardSize::STANDARD.indices_from_bitmask(bitboard)
 }
 
 pub fn can_place(bitboard: Bitboard, col: usize) -> bool {
-    if col >= COLS {
-        return false;
-    }
-    if (bitboard & get_top_mask(col)) > 0 {
-        return false;
-    }
-    true
+    BoardSize::STANDARD.can_place(bitboard, col)
 }
 
 pub fn is_board_full(bitboard: Bitboard) -> bool {
-    bitboard.count_ones() as usize >= ROWS * COLS
+    BoardSize::STANDARD.is_board_full(bitboard)
 }
 
 pub fn has_won(bitboard: Bitboard) -> bool {
-    let horizontal = bitboard & (bitboard << (ROWS + 1));
-    if horizontal & (horizontal << ((ROWS + 1) * 2)) > 0 {
-        return true;
+    Rules::STANDARD.has_won(bitboard)
+}
+
+pub fn is_game_over(
+    game_board: Bitboard,
+    player1_board: Bitboard,
+    player2_board: Bitboard,
+) -> bool {
+    Rules::STANDARD.is_game_over(game_board, player1_board, player2_board)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Player {
+    First,
+    Second,
+}
+
+impl Player {
+    pub fn opponent(self) -> Player {
+        match self {
+            Player::First => Player::Second,
+            Player::Second => Player::First,
+        }
     }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    InProgress,
+    Won(Player),
+    Draw,
+}
+
+/// A game in progress: the occupied cells, the stones of the side to move
+/// and every column played since the position was created.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Position {
+    rules: Rules,
+    mask: Bitboard,
+    current: Bitboard,
+    moves: Vec<usize>,
+}
 
-    let vertical = bitboard & (bitboard << 1);
-    if vertical & (vertical << 2) > 0 {
-        return true;
+impl Position {
+    pub fn new() -> Position {
+        Position::default()
     }
 
-    let main_diagonal = bitboard & (bitboard << (ROWS + 2));
-    if main_diagonal & (main_diagonal << ((ROWS + 2) * 2)) > 0 {
-        return true;
+    pub fn with_rules(rules: Rules) -> Position {
+        Position {
+            rules,
+            ..Position::default()
+        }
     }
 
-    let anti_diagonal = bitboard & (bitboard << ROWS);
-    if anti_diagonal & (anti_diagonal << (ROWS * 2)) > 0 {
-        return true;
+    /// Builds a position from the stones of each player, with no move
+    /// history. Fails when the boards could not come from a legal game.
+    pub fn from_boards(
+        rules: Rules,
+        first: Bitboard,
+        second: Bitboard,
+    ) -> Result<Position, EngineError> {
+        if first & second > 0 {
+            return Err(EngineError::OverlappingBoards);
+        }
+
+        let size = rules.size();
+        let mask = first | second;
+        let floating = mask & !(mask << 1) & !size.bottom_row_mask();
+        if mask & !size.board_mask() > 0 || floating > 0 {
+            return Err(EngineError::UnreachablePosition);
+        }
+
+        let first_count = first.count_ones();
+        let second_count = second.count_ones();
+        if first_count != second_count && first_count != second_count + 1 {
+            return Err(EngineError::UnreachablePosition);
+        }
+
+        // Only the player who moved last can have a line.
+        let first_won = rules.has_won(first);
+        let second_won = rules.has_won(second);
+        if (first_won && first_count == second_count) || (second_won && first_count > second_count)
+        {
+            return Err(EngineError::UnreachablePosition);
+        }
+
+        let current = if mask.count_ones().is_multiple_of(2) {
+            first
+        } else {
+            second
+        };
+        Ok(Position {
+            rules,
+            mask,
+            current,
+            moves: Vec::new(),
+        })
     }
 
-    false
-}
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
 
-pub fn is_game_over(
-    game_board: Bitboard,
-    player1_board: Bitboard,
-    player2_board: Bitboard,
-) -> bool {
-    if has_won(player1_board) || has_won(player2_board) {
-        return true;
+    pub fn size(&self) -> BoardSize {
+        self.rules.size()
+    }
+
+    /// Every occupied cell.
+    pub fn mask(&self) -> Bitboard {
+        self.mask
+    }
+
+    /// Stones of the side to move.
+    pub fn current(&self) -> Bitboard {
+        self.current
+    }
+
+    /// Stones of the side that just moved.
+    pub fn opponent(&self) -> Bitboard {
+        self.mask ^ self.current
+    }
+
+    pub fn board(&self, player: Player) -> Bitboard {
+        if player == self.side_to_move() {
+            self.current()
+        } else {
+            self.opponent()
+        }
+    }
+
+    /// Identifies the position for a given board size: adding the mask to
+    /// the stones of the side to move sets the cell above each column's
+    /// top stone, so every arrangement gets a different number.
+    pub fn key(&self) -> Bitboard {
+        self.current + self.mask
     }
-    if is_board_full(game_board) {
-        return true;
+
+    pub fn moves(&self) -> &[usize] {
+        &self.moves
+    }
+
+    /// Number of stones on the board, including any placed before the
+    /// recorded move history.
+    pub fn moves_played(&self) -> usize {
+        self.mask.count_ones() as usize
+    }
+
+    pub fn side_to_move(&self) -> Player {
+        if self.moves_played().is_multiple_of(2) {
+            Player::First
+        } else {
+            Player::Second
+        }
+    }
+
+    /// The owner of a cell, or `None` when it is empty or off the board.
+    pub fn cell(&self, row: usize, col: usize) -> Option<Player> {
+        if row >= self.size().rows() || col >= self.size().cols() {
+            return None;
+        }
+        let bit: Bitboard = 1 << (col * self.size().stride() + row);
+        if self.board(Player::First) & bit > 0 {
+            Some(Player::First)
+        } else if self.board(Player::Second) & bit > 0 {
+            Some(Player::Second)
+        } else {
+            None
+        }
+    }
+
+    pub fn can_play(&self, col: usize) -> bool {
+        self.size().can_place(self.mask, col)
+    }
+
+    /// Checks that the side to move may drop a stone in `col`.
+    pub fn check_move(&self, col: usize) -> Result<(), EngineError> {
+        let cols = self.size().cols();
+        if col >= cols {
+            return Err(EngineError::ColumnOutOfRange { col, cols });
+        }
+        if self.is_game_over() {
+            return Err(EngineError::GameOver);
+        }
+        if !self.can_play(col) {
+            return Err(EngineError::ColumnFull { col });
+        }
+        Ok(())
+    }
+
+    /// Drops a stone for the side to move, leaving the position untouched
+    /// when the move is illegal.
+    pub fn play(&mut self, col: usize) -> Result<(), EngineError> {
+        self.check_move(col)?;
+        self.play_unchecked(col);
+        Ok(())
+    }
+
+    /// Drops a stone without checking whether the game is already over.
+    /// The caller must have checked `can_play`.
+    pub fn play_unchecked(&mut self, col: usize) {
+        self.current ^= self.mask;
+        self.mask |= self.size().next_row(self.mask, col);
+        self.moves.push(col);
+    }
+
+    /// Takes back the last recorded move and returns its column.
+    pub fn undo(&mut self) -> Option<usize> {
+        let col = self.moves.pop()?;
+        let column = self.mask & self.size().col_mask(col);
+        let top: Bitboard = 1 << (Bitboard::BITS - 1 - column.leading_zeros());
+        self.mask ^= top;
+        self.current ^= self.mask;
+        Some(col)
+    }
+
+    pub fn is_game_over(&self) -> bool {
+        self.rules
+            .is_game_over(self.mask, self.current, self.opponent())
+    }
+
+    pub fn outcome(&self) -> Outcome {
+        for player in [Player::First, Player::Second] {
+            if self.rules.has_won(self.board(player)) {
+                return Outcome::Won(player);
+            }
+        }
+        if self.size().is_board_full(self.mask) {
+            return Outcome::Draw;
+        }
+        Outcome::InProgress
+    }
+
+    /// Stones of the winner's line, or an empty board while nobody has won.
+    pub fn winning_line(&self) -> Bitboard {
+        match self.outcome() {
+            Outcome::Won(player) => self.rules.winning_line(self.board(player)),
+            _ => 0,
+        }
+    }
+
+    /// Empty cells where a stone of `player` would complete a line,
+    /// whether or not they can be played yet.
+    pub fn threats(&self, player: Player) -> Bitboard {
+        self.rules.winning_cells(self.board(player)) & !self.mask
+    }
+
+    /// Cells where the side to move wins at once.
+    pub fn winning_moves(&self) -> Bitboard {
+        self.threats(self.side_to_move()) & self.size().playable(self.mask)
+    }
+
+    /// Cells the side to move can play without letting the opponent win
+    /// on the next move: winning moves, and otherwise moves that block
+    /// every immediate threat and do not fill the cell below another one.
+    /// Empty when every move loses at once.
+    pub fn non_losing_moves(&self) -> Bitboard {
+        let wins = self.winning_moves();
+        let mut moves = self.size().playable(self.mask);
+        let opponent_threats = self.threats(self.side_to_move().opponent());
+
+        let forced = moves & opponent_threats;
+        if forced > 0 {
+            // Two threats at once cannot both be blocked.
+            if forced & (forced - 1) > 0 {
+                return wins;
+            }
+            moves = forced;
+        }
+        wins | (moves & !(opponent_threats >> 1))
+    }
+
+    /// Cells the side to move can play but that let the opponent win on
+    /// the next move.
+    pub fn losing_moves(&self) -> Bitboard {
+        self.size().playable(self.mask) & !self.non_losing_moves()
     }
-    false
 }
 
 #[cfg(test)]
+#[allow(clippy::unusual_byte_groupings, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -184,4 +696,301 @@ mod tests {
         let anti_diagonal_win: Bitboard = 0b000100_0_001000_0_010000_0_100000;
         assert_eq!(has_won(anti_diagonal_win), true);
     }
+
+    #[test]
+    fn test_position_play_and_undo() {
+        let mut position = Position::new();
+        assert_eq!(position.side_to_move(), Player::First);
+
+        assert_eq!(position.play(3), Ok(()));
+        assert_eq!(position.play(3), Ok(()));
+        assert_eq!(position.play(4), Ok(()));
+        assert_eq!(position.moves(), &[3, 3, 4]);
+        assert_eq!(position.moves_played(), 3);
+        assert_eq!(position.side_to_move(), Player::Second);
+        assert_eq!(position.board(Player::First), 0b000001_0_000001 << 21);
+        assert_eq!(position.board(Player::Second), 0b000010 << 21);
+
+        assert_eq!(position.undo(), Some(4));
+        assert_eq!(position.undo(), Some(3));
+        assert_eq!(position.board(Player::First), 0b000001 << 21);
+        assert_eq!(position.board(Player::Second), 0);
+        assert_eq!(position.undo(), Some(3));
+        assert_eq!(position.undo(), None);
+        assert_eq!(position, Position::new());
+    }
+
+    #[test]
+    fn test_position_rejects_full_column() {
+        let mut position = Position::new();
+        for _ in 0..ROWS {
+            assert_eq!(position.play(0), Ok(()));
+        }
+        assert_eq!(position.play(0), Err(EngineError::ColumnFull { col: 0 }));
+        assert_eq!(
+            position.play(COLS),
+            Err(EngineError::ColumnOutOfRange {
+                col: COLS,
+                cols: COLS
+            })
+        );
+        assert_eq!(position.moves_played(), ROWS);
+    }
+
+    #[test]
+    fn test_position_rejects_move_after_win() {
+        let mut position = Position::new();
+        for col in [0, 1, 0, 1, 0, 1, 0] {
+            assert_eq!(position.play(col), Ok(()));
+        }
+        assert_eq!(position.play(1), Err(EngineError::GameOver));
+        assert_eq!(position.moves_played(), 7);
+    }
+
+    #[test]
+    fn test_position_from_boards() {
+        let first: Bitboard = 0b000001 << 21;
+        let second: Bitboard = 0b000010 << 21;
+        let position = Position::from_boards(Rules::STANDARD, first, second).unwrap();
+        assert_eq!(position.side_to_move(), Player::First);
+        assert_eq!(position.board(Player::Second), second);
+
+        assert_eq!(
+            Position::from_boards(Rules::STANDARD, first, first),
+            Err(EngineError::OverlappingBoards)
+        );
+        assert_eq!(
+            Position::from_boards(Rules::STANDARD, 0, first),
+            Err(EngineError::UnreachablePosition)
+        );
+        assert_eq!(
+            Position::from_boards(Rules::STANDARD, 1 << 6, 0),
+            Err(EngineError::UnreachablePosition)
+        );
+
+        let first_line: Bitboard = 0b1111;
+        let second_line: Bitboard = 0b1111 << 7;
+        assert_eq!(
+            Position::from_boards(Rules::STANDARD, first_line, second_line),
+            Err(EngineError::UnreachablePosition)
+        );
+    }
+
+    #[test]
+    fn test_board_size() {
+        assert_eq!(BoardSize::new(7, 6), Some(BoardSize::STANDARD));
+        assert_eq!(BoardSize::new(9, 6).map(|size| size.cells()), Some(54));
+        assert!(BoardSize::new(10, 8).is_some());
+        assert_eq!(BoardSize::new(10, 12), None);
+        assert_eq!(BoardSize::new(36, 2), None);
+        assert_eq!(BoardSize::new(0, 6), None);
+
+        let size = BoardSize::new(8, 7).unwrap();
+        assert_eq!(size.top_mask(1), 0b1000000_0_0000000);
+        assert_eq!(size.col_mask(1), 0b1111111_0_0000000);
+        assert_eq!(size.indices_from_bitmask(0b1000000_0_0000000), Some((6, 1)));
+        assert_eq!(size.can_place(size.col_mask(1), 1), false);
+        assert_eq!(size.can_place(0, 8), false);
+        assert_eq!(size.is_board_full(size.board_mask()), true);
+        assert_eq!(size.board_mask().count_ones(), 56);
+    }
+
+    #[test]
+    fn test_has_won_on_wide_board() {
+        let size = BoardSize::new(10, 8).unwrap();
+        let rules = Rules::new(size, 4).unwrap();
+        let stride = size.stride();
+
+        let horizontal_win: Bitboard = (6..10).map(|col| 1 << (col * stride)).sum();
+        assert_eq!(rules.has_won(horizontal_win), true);
+
+        let split_row: Bitboard = [0, 1, 2, 4, 5, 6]
+            .iter()
+            .map(|col| 1 << (col * stride))
+            .sum();
+        assert_eq!(rules.has_won(split_row), false);
+
+        let vertical_win: Bitboard = 0b11110000 << (9 * stride);
+        assert_eq!(rules.has_won(vertical_win), true);
+
+        let main_diagonal_win: Bitboard = (0..4).map(|i| 1 << ((i + 5) * stride + i + 4)).sum();
+        assert_eq!(rules.has_won(main_diagonal_win), true);
+
+        let anti_diagonal_win: Bitboard = (0..4).map(|i| 1 << ((i + 6) * stride + 7 - i)).sum();
+        assert_eq!(rules.has_won(anti_diagonal_win), true);
+
+        // Top of one column next to the bottom of the next is not a line.
+        let wrapped: Bitboard = 0b11000000 | (0b11 << stride);
+        assert_eq!(rules.has_won(wrapped), false);
+    }
+
+    #[test]
+    fn test_has_won_connect_n() {
+        let size = BoardSize::new(9, 7).unwrap();
+        let stride = size.stride();
+        let connect_three = Rules::new(size, 3).unwrap();
+        let connect_five = Rules::new(size, 5).unwrap();
+        let connect_six = Rules::new(size, 6).unwrap();
+
+        let three: Bitboard = 0b111;
+        assert_eq!(connect_three.has_won(three), true);
+        assert_eq!(Rules::STANDARD.has_won(three), false);
+
+        let four_horizontal: Bitboard = (2..6).map(|col| 1 << (col * stride)).sum();
+        assert_eq!(connect_five.has_won(four_horizontal), false);
+
+        let five_horizontal: Bitboard = (2..7).map(|col| 1 << (col * stride)).sum();
+        assert_eq!(connect_five.has_won(five_horizontal), true);
+        assert_eq!(connect_six.has_won(five_horizontal), false);
+
+        let five_diagonal: Bitboard = (0..5).map(|i| 1 << ((i + 3) * stride + i + 1)).sum();
+        assert_eq!(connect_five.has_won(five_diagonal), true);
+
+        let six_vertical: Bitboard = 0b1111110 << (4 * stride);
+        assert_eq!(connect_six.has_won(six_vertical), true);
+
+        assert_eq!(Rules::new(size, 10), None);
+        assert_eq!(Rules::new(size, 1), None);
+    }
+
+    #[test]
+    fn test_position_with_rules() {
+        let size = BoardSize::new(9, 7).unwrap();
+        let mut position = Position::with_rules(Rules::new(size, 5).unwrap());
+        for _ in 0..4 {
+            position.play(8).unwrap();
+            position.play(7).unwrap();
+        }
+        assert_eq!(position.is_game_over(), false);
+        position.play(8).unwrap();
+        assert_eq!(position.is_game_over(), true);
+        assert_eq!(
+            position.board(Player::First),
+            0b11111 << (8 * size.stride())
+        );
+    }
+
+    #[test]
+    fn test_outcome() {
+        let mut position = Position::new();
+        assert_eq!(position.outcome(), Outcome::InProgress);
+        assert_eq!(position.winning_line(), 0);
+
+        for col in [3, 0, 4, 0, 5, 1] {
+            position.play(col).unwrap();
+        }
+        assert_eq!(position.outcome(), Outcome::InProgress);
+        position.play(2).unwrap();
+        assert_eq!(position.outcome(), Outcome::Won(Player::First));
+        assert_eq!(
+            BoardSize::STANDARD.cells_from_bitmask(position.winning_line()),
+            vec![(0, 2), (0, 3), (0, 4), (0, 5)]
+        );
+
+        let mut position = Position::new();
+        for col in [0, 6, 1, 6, 2, 6, 5, 6] {
+            position.play(col).unwrap();
+        }
+        assert_eq!(position.outcome(), Outcome::Won(Player::Second));
+        assert_eq!(position.winning_line().count_ones(), 4);
+    }
+
+    #[test]
+    fn test_winning_line_longer_than_connect() {
+        let mut position = Position::new();
+        for col in [0, 0, 1, 1, 2, 2, 4, 4, 5, 5, 6, 6, 3] {
+            position.play(col).unwrap();
+        }
+        assert_eq!(position.outcome(), Outcome::Won(Player::First));
+        assert_eq!(
+            position.winning_line(),
+            BoardSize::STANDARD.bottom_row_mask()
+        );
+
+        // Walking back along a diagonal shifts further than the board is
+        // wide.
+        let size = BoardSize::new(35, 2).unwrap();
+        let rules = Rules::new(size, 35).unwrap();
+        assert_eq!(
+            rules.winning_line(size.bottom_row_mask()),
+            size.bottom_row_mask()
+        );
+    }
+
+    #[test]
+    fn test_outcome_draw() {
+        let o = Player::Second;
+        let x = Player::First;
+        let rows = [
+            [x, o, x, o, x, o, x],
+            [x, o, x, o, x, o, x],
+            [o, x, o, x, o, x, o],
+            [o, x, o, x, o, x, o],
+            [x, o, x, o, x, o, x],
+            [o, x, o, x, o, x, o],
+        ];
+        let mut first: Bitboard = 0;
+        let mut second: Bitboard = 0;
+        for (row, cells) in rows.iter().enumerate() {
+            for (col, &owner) in cells.iter().enumerate() {
+                let bit: Bitboard = 1 << (col * 7 + row);
+                if owner == Player::First {
+                    first |= bit;
+                } else {
+                    second |= bit;
+                }
+            }
+        }
+        let position = Position::from_boards(Rules::STANDARD, first, second).unwrap();
+        assert_eq!(position.outcome(), Outcome::Draw);
+        assert_eq!(position.winning_line(), 0);
+    }
+
+    #[test]
+    fn test_threat_queries() {
+        let size = BoardSize::STANDARD;
+        // First, to move, wins in columns 2 and 6 and must otherwise
+        // block column 0.
+        let position = crate::notation::parse_moves(Rules::STANDARD, "415161").unwrap();
+        assert_eq!(size.columns(position.winning_moves()), [2, 6]);
+        assert_eq!(size.columns(position.non_losing_moves()), [0, 2, 6]);
+        assert_eq!(size.columns(position.losing_moves()), [1, 3, 4, 5]);
+        assert_eq!(size.columns(position.threats(Player::Second)), [0]);
+
+        // Second cannot block both ends of the open three.
+        let position = crate::notation::parse_moves(Rules::STANDARD, "44556").unwrap();
+        assert_eq!(position.winning_moves(), 0);
+        assert_eq!(position.non_losing_moves(), 0);
+        assert_eq!(size.columns(position.losing_moves()), [0, 1, 2, 3, 4, 5, 6]);
+
+        // Second playing under First's threat in column 3 hands it the win.
+        let position = crate::notation::parse_moves(Rules::STANDARD, "2113372").unwrap();
+        assert_eq!(size.columns(position.threats(Player::First)), [3]);
+        assert_eq!(position.winning_moves(), 0);
+        assert_eq!(size.columns(position.losing_moves()), [3]);
+    }
+
+    #[test]
+    fn test_winning_cells() {
+        let rules = Rules::STANDARD;
+        let row = 0b1_0000001_0000001;
+        assert_eq!(rules.winning_cells(row), 1 << 21);
+
+        let gap = 0b1_0000000_0000001_0000001;
+        assert_eq!(rules.winning_cells(gap), 1 << 14);
+
+        let column = 0b111;
+        assert_eq!(rules.winning_cells(column), 0b1000);
+
+        // Three stones at the top of a column would need the sentinel.
+        let top = 0b111000;
+        assert_eq!(rules.winning_cells(top), 0b100);
+
+        let size = BoardSize::STANDARD;
+        assert_eq!(size.playable(0), size.bottom_row_mask());
+        assert!(size.centre_out().eq([3, 2, 4, 1, 5, 0, 6]));
+        let even = BoardSize::new(8, 7).unwrap();
+        assert!(even.centre_out().eq([4, 3, 5, 2, 6, 1, 7, 0]));
+        assert_eq!(size.playable(0b111111), size.bottom_row_mask() & !1);
+    }
 }
@@ -0,0 +1,305 @@
+use crate::bitboard::*;
+use crate::bot::{SearchLimits, analyse, is_decisive, search};
+use crate::eval::EvalParams;
+use crate::notation::{NotationError, char_to_column, column_to_char, format_moves, parse_moves};
+use crate::rng::Rng;
+use crate::transposition::TranspositionTable;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Search depth used to judge openings.
+pub const BOOK_DEPTH: usize = 10;
+/// Score from even within which openings count as balanced.
+pub const BOOK_WINDOW: f32 = 2.0;
+
+/// Every position reached by `plies` legal moves from the start that is
+/// still in progress.
+pub fn openings(rules: Rules, plies: usize) -> Vec<Position> {
+    let mut positions = vec![Position::with_rules(rules)];
+    for _ in 0..plies {
+        positions = positions
+            .iter()
+            .flat_map(|position| {
+                let size = position.size();
+                size.columns(size.playable(position.mask()))
+                    .into_iter()
+                    .map(move |col| {
+                        let mut next = position.clone();
+                        next.play_unchecked(col);
+                        next
+                    })
+            })
+            .filter(|position| !position.is_game_over())
+            .collect();
+    }
+    positions
+}
+
+/// Every opening of `plies` moves whose search score at `depth` is within
+/// `window` of even. Openings the search finds a forced result in are
+/// left out whatever their score.
+pub fn balanced_openings(rules: Rules, plies: usize, depth: usize, window: f32) -> Vec<Position> {
+    openings(rules, plies)
+        .into_par_iter()
+        .map_init(TranspositionTable::default, |table, position| {
+            let score = search(&position, SearchLimits::depth(depth), table)
+                .unwrap()
+                .score;
+            (!is_decisive(score) && score.abs() <= window).then_some(position)
+        })
+        .flatten()
+        .collect()
+}
+
+/// Recommended moves for positions early in the game. Transpositions
+/// share an entry, since positions are looked up by their stones rather
+/// than the order they were played in.
+#[derive(Clone, Debug)]
+pub struct OpeningBook {
+    rules: Rules,
+    entries: Vec<(Position, Vec<usize>)>,
+    index: HashMap<Bitboard, usize>,
+}
+
+impl OpeningBook {
+    pub fn new(rules: Rules) -> OpeningBook {
+        OpeningBook {
+            rules,
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    /// Builds a book of every line `plies` moves deep in which each move
+    /// scores within `window` of the best one when analysed to `depth`.
+    /// Lines stop early at positions the search finds a forced result in.
+    pub fn generate(rules: Rules, plies: usize, depth: usize, window: f32) -> OpeningBook {
+        let mut book = OpeningBook::new(rules);
+        let mut frontier = vec![Position::with_rules(rules)];
+        for _ in 0..plies {
+            let analysed: Vec<(Position, Vec<usize>)> = frontier
+                .par_iter()
+                .map_init(TranspositionTable::default, |table, position| {
+                    let analysis = analyse(
+                        position,
+                        SearchLimits::depth(depth),
+                        &EvalParams::default(),
+                        table,
+                    )
+                    .unwrap();
+                    let best = analysis.columns[0].score;
+                    if is_decisive(best) {
+                        return None;
+                    }
+                    let cols = analysis
+                        .columns
+                        .iter()
+                        .filter(|column| column.score >= best - window)
+                        .map(|column| column.col)
+                        .collect();
+                    Some((position.clone(), cols))
+                })
+                .flatten()
+                .collect();
+
+            let mut seen = HashSet::new();
+            frontier.clear();
+            for (position, cols) in analysed {
+                for &col in &cols {
+                    let mut next = position.clone();
+                    next.play_unchecked(col);
+                    if !next.is_game_over() && seen.insert(next.key()) {
+                        frontier.push(next);
+                    }
+                }
+                book.insert(position, cols);
+            }
+        }
+        book
+    }
+
+    /// Reads a book in the format `parse` accepts.
+    pub fn load(rules: Rules, path: impl AsRef<Path>) -> Result<OpeningBook, BookError> {
+        OpeningBook::parse(rules, &fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), BookError> {
+        Ok(fs::write(path, self.to_string())?)
+    }
+
+    /// Parses lines of a move string, or `-` for the empty board, followed
+    /// by the recommended columns written together in the same notation.
+    /// `#` starts a comment.
+    pub fn parse(rules: Rules, text: &str) -> Result<OpeningBook, BookError> {
+        let mut book = OpeningBook::new(rules);
+        for (line, content) in text.lines().enumerate() {
+            let content = content.split('#').next().unwrap_or_default();
+            let mut fields = content.split_whitespace();
+            let Some(moves) = fields.next() else {
+                continue;
+            };
+            let position = parse_moves(rules, if moves == "-" { "" } else { moves })
+                .map_err(|error| BookError::InvalidMoves { line, error })?;
+            let cols = fields.next().ok_or(BookError::MissingMoves { line })?;
+            let cols = cols
+                .chars()
+                .map(|symbol| {
+                    char_to_column(symbol)
+                        .filter(|&col| col < rules.size().cols() && position.can_play(col))
+                        .ok_or(BookError::InvalidColumn {
+                            line,
+                            found: symbol,
+                        })
+                })
+                .collect::<Result<_, _>>()?;
+            book.insert(position, cols);
+        }
+        Ok(book)
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// Number of positions in the book.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Sets the recommended moves of `position`, replacing any it had.
+    pub fn insert(&mut self, position: Position, cols: Vec<usize>) {
+        match self.index.get(&position.key()) {
+            Some(&index) => self.entries[index].1 = cols,
+            None => {
+                self.index.insert(position.key(), self.entries.len());
+                self.entries.push((position, cols));
+            }
+        }
+    }
+
+    /// The recommended moves of `position`, or none when it is not in the
+    /// book.
+    pub fn moves(&self, position: &Position) -> &[usize] {
+        if position.rules() != self.rules {
+            return &[];
+        }
+        self.index
+            .get(&position.key())
+            .map_or(&[], |&index| &self.entries[index].1)
+    }
+
+    /// One of the recommended moves of `position`, chosen at random.
+    pub fn choose(&self, position: &Position, rng: &mut Rng) -> Option<usize> {
+        rng.choose(self.moves(position))
+    }
+}
+
+/// Writes the book in the format `OpeningBook::parse` reads.
+impl fmt::Display for OpeningBook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (position, cols) in &self.entries {
+            let moves = format_moves(position);
+            let cols: String = cols.iter().map(|&col| column_to_char(col)).collect();
+            let moves = if moves.is_empty() { "-" } else { &moves };
+            writeln!(f, "{} {}", moves, cols)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum BookError {
+    Io(io::Error),
+    MissingMoves { line: usize },
+    InvalidMoves { line: usize, error: NotationError },
+    InvalidColumn { line: usize, found: char },
+}
+
+impl fmt::Display for BookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BookError::Io(error) => write!(f, "{}", error),
+            BookError::MissingMoves { line } => {
+                write!(f, "line {}: missing recommended moves", line + 1)
+            }
+            BookError::InvalidMoves { line, error } => write!(f, "line {}: {}", line + 1, error),
+            BookError::InvalidColumn { line, found } => {
+                write!(f, "line {}: '{}' is not a legal column", line + 1, found)
+            }
+        }
+    }
+}
+
+impl Error for BookError {}
+
+impl From<io::Error> for BookError {
+    fn from(error: io::Error) -> BookError {
+        BookError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let book = OpeningBook::parse(Rules::STANDARD, "- 4 # centre\n\n44 35\n").unwrap();
+        assert_eq!(book.len(), 2);
+        assert_eq!(book.moves(&Position::new()), [3]);
+        let position = parse_moves(Rules::STANDARD, "44").unwrap();
+        assert_eq!(book.moves(&position), [2, 4]);
+        assert!(
+            book.moves(&parse_moves(Rules::STANDARD, "43").unwrap())
+                .is_empty()
+        );
+        let col = book.choose(&position, &mut Rng::new(1)).unwrap();
+        assert!(col == 2 || col == 4);
+        assert_eq!(book.to_string(), "- 4\n44 35\n");
+
+        assert!(matches!(
+            OpeningBook::parse(Rules::STANDARD, "44"),
+            Err(BookError::MissingMoves { line: 0 })
+        ));
+        assert!(matches!(
+            OpeningBook::parse(Rules::STANDARD, "- 4\n4444444 4"),
+            Err(BookError::InvalidMoves { line: 1, .. })
+        ));
+        assert!(matches!(
+            OpeningBook::parse(Rules::STANDARD, "444444 4"),
+            Err(BookError::InvalidColumn { line: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_openings() {
+        assert_eq!(openings(Rules::STANDARD, 0).len(), 1);
+        assert_eq!(openings(Rules::STANDARD, 2).len(), 49);
+    }
+
+    #[test]
+    fn test_generate() {
+        let book = OpeningBook::generate(Rules::STANDARD, 3, 6, 2.0);
+        assert!(!book.moves(&Position::new()).is_empty());
+        let text = book.to_string();
+        let parsed = OpeningBook::parse(Rules::STANDARD, &text).unwrap();
+        assert_eq!(parsed.len(), book.len());
+        assert_eq!(parsed.to_string(), text);
+        for (position, cols) in &book.entries {
+            assert!(position.moves_played() < 3);
+            assert!(!cols.is_empty());
+        }
+
+        let balanced = balanced_openings(Rules::STANDARD, 2, 6, 2.0);
+        assert!(!balanced.is_empty() && balanced.len() < 49);
+    }
+}
@@ -1,130 +1,759 @@
 use crate::bitboard::*;
+use crate::error::EngineError;
+use crate::eval::{EvalParams, Evaluator};
+use crate::solver::Value;
+use crate::transposition::{Bound, Entry, TableStats, TranspositionTable};
 use rayon::prelude::*;
+use std::ops::AddAssign;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
-pub const MIDDLE_MASK: Bitboard = 0b111111000000000000000000000;
-pub const MIDDLE_SCORE: f32 = 0.25;
+/// Score of a win, less the number of stones on the board when it
+/// happened, so faster wins score higher and slower losses score less
+/// badly. Heuristic scores are clamped to at most `WIN_SCORE - MAX_CELLS`,
+/// however large the evaluator's weights.
 pub const WIN_SCORE: f32 = 10000.0;
-pub const CONNECT_SCORE: [f32; 2] = [
-    1.0, // Connect two sore
-    8.0, // Connect three score
-];
+const MAX_CELLS: f32 = Bitboard::BITS as f32;
 
-fn evaluate(bitboard: Bitboard) -> f32 {
-    if has_won(bitboard) {
-        return WIN_SCORE;
+/// Whether `score` comes from a won or lost end of the game rather than the
+/// heuristic evaluation.
+pub fn is_decisive(score: f32) -> bool {
+    score.abs() > WIN_SCORE - MAX_CELLS
+}
+
+/// Outcome of a search from the side to move's point of view.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SearchResult {
+    pub best_move: usize,
+    pub score: f32,
+    pub stats: SearchStats,
+    moves_played: usize,
+}
+
+/// Work done by a search, up to the end of its last completed iteration
+/// or, in the final result, including any unfinished one.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct SearchStats {
+    /// Depth of the last completed iteration.
+    pub depth: usize,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    /// Nodes left early because a move reached beta.
+    pub cutoffs: u64,
+    /// Cutoffs made by the first move tried, a measure of move ordering.
+    pub first_move_cutoffs: u64,
+    pub table_probes: u64,
+    pub table_hits: u64,
+}
+
+impl SearchStats {
+    pub fn nodes_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        self.nodes as f64 / seconds
+    }
+
+    /// Share of nodes that ended in a cutoff.
+    pub fn cutoff_rate(&self) -> f64 {
+        ratio(self.cutoffs, self.nodes)
+    }
+
+    /// Share of cutoffs made by the first move tried.
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        ratio(self.first_move_cutoffs, self.cutoffs)
+    }
+
+    pub fn table_hit_rate(&self) -> f64 {
+        ratio(self.table_hits, self.table_probes)
+    }
+}
+
+/// Totals over several searches, keeping the deepest depth.
+impl AddAssign for SearchStats {
+    fn add_assign(&mut self, other: SearchStats) {
+        self.depth = self.depth.max(other.depth);
+        self.nodes += other.nodes;
+        self.elapsed += other.elapsed;
+        self.cutoffs += other.cutoffs;
+        self.first_move_cutoffs += other.first_move_cutoffs;
+        self.table_probes += other.table_probes;
+        self.table_hits += other.table_hits;
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    part as f64 / total as f64
+}
+
+impl SearchResult {
+    /// Moves the side to move needs to force a win, counting only its own.
+    pub fn win_in(&self) -> Option<usize> {
+        win_in(self.score, self.moves_played)
+    }
+
+    /// Moves the opponent needs to force a win against best defence,
+    /// counting only its own.
+    pub fn loss_in(&self) -> Option<usize> {
+        loss_in(self.score, self.moves_played)
+    }
+}
+
+// Plies from a position with `moves_played` stones to the end of the game,
+// if `score` is decisive.
+fn plies_to_end(score: f32, moves_played: usize) -> Option<usize> {
+    if !is_decisive(score) {
+        return None;
     }
-    let mut total_score: f32 = 0.0;
+    let end = (WIN_SCORE - score.abs()) as usize;
+    end.checked_sub(moves_played)
+}
 
-    let middle = bitboard & MIDDLE_MASK;
-    total_score += middle.count_ones() as f32 * MIDDLE_SCORE;
+fn win_in(score: f32, moves_played: usize) -> Option<usize> {
+    match plies_to_end(score, moves_played) {
+        Some(plies) if score > 0.0 => Some(plies.div_ceil(2)),
+        _ => None,
+    }
+}
 
-    let mut horizontal = bitboard & (bitboard << (ROWS + 1));
-    let mut vertical = bitboard & (bitboard << 1);
-    let mut main_diagonal = bitboard & (bitboard << (ROWS + 2));
-    let mut anti_diagonal = bitboard & (bitboard << ROWS);
+fn loss_in(score: f32, moves_played: usize) -> Option<usize> {
+    match plies_to_end(score, moves_played) {
+        Some(plies) if score < 0.0 => Some(plies / 2),
+        _ => None,
+    }
+}
 
-    for i in 0..2 {
-        let all_direction = horizontal | vertical | main_diagonal | anti_diagonal;
-        total_score += all_direction.count_ones() as f32 * CONNECT_SCORE[i];
+/// Budget for an iterative deepening search. Unset limits are unbounded;
+/// the search also stops once it has looked to the end of the game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<usize>,
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: usize) -> SearchLimits {
+        SearchLimits {
+            depth: Some(depth),
+            ..SearchLimits::default()
+        }
+    }
 
-        horizontal &= horizontal << (ROWS + 1);
-        vertical &= vertical << 1;
-        main_diagonal &= main_diagonal << (ROWS + 2);
-        anti_diagonal &= anti_diagonal << ROWS;
+    pub fn time(time: Duration) -> SearchLimits {
+        SearchLimits {
+            time: Some(time),
+            ..SearchLimits::default()
+        }
+    }
+
+    pub fn nodes(nodes: u64) -> SearchLimits {
+        SearchLimits {
+            nodes: Some(nodes),
+            ..SearchLimits::default()
+        }
+    }
+}
+
+/// Lets another thread stop a running search. Clones share the same flag,
+/// so a front-end keeps one and hands a clone to the search. Once stopped,
+/// a handle stops every later search it is given.
+#[derive(Clone, Default, Debug)]
+pub struct SearchHandle {
+    stopped: Arc<AtomicBool>,
+}
+
+impl SearchHandle {
+    pub fn new() -> SearchHandle {
+        SearchHandle::default()
+    }
+
+    /// Asks the search to return the move of its last completed iteration.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
     }
-    total_score
 }
 
-fn minmax(
-    game_board: Bitboard,
-    player_board: Bitboard,
-    bot_board: Bitboard,
+// The clock is only read every this many nodes.
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// State shared by every thread of one search.
+struct Search<'a> {
+    evaluator: &'a dyn Evaluator,
+    table: &'a TranspositionTable,
+    handle: &'a SearchHandle,
+    started: Instant,
+    table_before: TableStats,
+    deadline: Option<Instant>,
+    node_limit: Option<u64>,
+    nodes: AtomicU64,
+    cutoffs: AtomicU64,
+    first_move_cutoffs: AtomicU64,
+    // Set once the first iteration is done, so there is always a move.
+    interruptible: AtomicBool,
+    stopped: AtomicBool,
+}
+
+impl Search<'_> {
+    fn new<'a>(
+        evaluator: &'a dyn Evaluator,
+        table: &'a TranspositionTable,
+        handle: &'a SearchHandle,
+        limits: SearchLimits,
+    ) -> Search<'a> {
+        let started = Instant::now();
+        Search {
+            evaluator,
+            table,
+            handle,
+            started,
+            table_before: table.stats(),
+            deadline: limits.time.map(|time| started + time),
+            node_limit: limits.nodes,
+            nodes: AtomicU64::new(0),
+            cutoffs: AtomicU64::new(0),
+            first_move_cutoffs: AtomicU64::new(0),
+            interruptible: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Counts a node and returns whether the search should give up.
+    fn visit(&self) -> bool {
+        let nodes = self.nodes.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.stopped.load(Ordering::Relaxed) {
+            return true;
+        }
+        if !self.interruptible.load(Ordering::Relaxed) {
+            return false;
+        }
+        let out_of_nodes = self.node_limit.is_some_and(|limit| nodes >= limit);
+        let cancelled = self.handle.is_stopped();
+        let out_of_time = nodes.is_multiple_of(TIME_CHECK_INTERVAL)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        if out_of_nodes || out_of_time || cancelled {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    fn record_cutoff(&self, first_move: bool) {
+        self.cutoffs.fetch_add(1, Ordering::Relaxed);
+        if first_move {
+            self.first_move_cutoffs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn stats(&self, depth: usize) -> SearchStats {
+        let table = self.table.stats();
+        SearchStats {
+            depth,
+            nodes: self.nodes.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+            cutoffs: self.cutoffs.load(Ordering::Relaxed),
+            first_move_cutoffs: self.first_move_cutoffs.load(Ordering::Relaxed),
+            table_probes: table.probes - self.table_before.probes,
+            table_hits: table.hits - self.table_before.hits,
+        }
+    }
+}
+
+/// Move ordering hints gathered during a search. Killers are the columns
+/// that last caused a cutoff at each ply; the history table scores cells by
+/// how much search their cutoffs saved, per side.
+struct Heuristics {
+    killers: Vec<[Option<usize>; 2]>,
+    history: Vec<u64>,
+}
+
+impl Heuristics {
+    fn new(size: BoardSize) -> Heuristics {
+        Heuristics {
+            killers: vec![[None; 2]; size.cells() + 1],
+            history: vec![0; 2 * Bitboard::BITS as usize],
+        }
+    }
+
+    fn history_index(side: Player, cell: Bitboard) -> usize {
+        side as usize * Bitboard::BITS as usize + cell.trailing_zeros() as usize
+    }
+
+    fn record_cutoff(&mut self, position: &Position, col: usize, cell: Bitboard, depth: usize) {
+        let killers = &mut self.killers[position.moves_played()];
+        if killers[0] != Some(col) {
+            killers[1] = killers[0];
+            killers[0] = Some(col);
+        }
+        let index = Heuristics::history_index(position.side_to_move(), cell);
+        self.history[index] += (depth * depth) as u64;
+    }
+}
+
+/// Columns of the `candidates` cells in search order: immediate wins,
+/// blocks of the opponent's immediate wins, the table move, killers, then
+/// by history. Ties keep centre-out order. Fills `moves` with the column,
+/// its cell and whether it is a win or block, and returns how many there
+/// are.
+fn order_moves(
+    position: &Position,
+    candidates: Bitboard,
+    table_move: Option<usize>,
+    heuristics: &Heuristics,
+    moves: &mut [(usize, Bitboard, bool); MAX_COLS],
+) -> usize {
+    let size = position.size();
+    let side = position.side_to_move();
+    let wins = position.winning_moves();
+    let blocks = position.threats(side.opponent()) & candidates;
+    let killers = heuristics.killers[position.moves_played()];
+
+    let mut priorities = [(0, 0); MAX_COLS];
+    let mut count = 0;
+    for col in size.centre_out() {
+        let cell = candidates & size.col_mask(col);
+        if cell == 0 {
+            continue;
+        }
+        let tier = if cell & wins > 0 {
+            5
+        } else if cell & blocks > 0 {
+            4
+        } else if Some(col) == table_move {
+            3
+        } else if Some(col) == killers[0] {
+            2
+        } else if Some(col) == killers[1] {
+            1
+        } else {
+            0
+        };
+        let priority = (
+            tier,
+            heuristics.history[Heuristics::history_index(side, cell)],
+        );
+
+        let mut index = count;
+        while index > 0 && priorities[index - 1] < priority {
+            priorities[index] = priorities[index - 1];
+            moves[index] = moves[index - 1];
+            index -= 1;
+        }
+        priorities[index] = priority;
+        moves[index] = (col, cell, tier >= 4);
+        count += 1;
+    }
+    count
+}
+
+/// Scores `position` from the point of view of the side to move. Once the
+/// search is stopped the returned score is meaningless and nothing more is
+/// written to the table.
+fn negamax(
+    position: &mut Position,
     depth: usize,
     mut alpha: f32,
     mut beta: f32,
-    maximizing: bool,
+    search: &Search,
+    heuristics: &mut Heuristics,
 ) -> f32 {
-    if depth == 0 || is_game_over(game_board, player_board, bot_board) {
-        return evaluate(bot_board) - evaluate(player_board);
-    }
-
-    if maximizing {
-        let mut max_eval = f32::NEG_INFINITY;
-        for col in 0..COLS {
-            if can_place(game_board, col) {
-                let next_row = get_next_row(game_board, col);
-                let new_game_board = game_board | next_row;
-                let new_bot_board = bot_board ^ next_row;
-
-                let eval_score = minmax(
-                    new_game_board,
-                    player_board,
-                    new_bot_board,
-                    depth - 1,
-                    alpha,
-                    beta,
-                    false,
-                );
+    if search.visit() {
+        return 0.0;
+    }
+    let table = search.table;
+    let moves_played = position.moves_played() as f32;
+    match position.outcome() {
+        // Only the player who just moved can have won.
+        Outcome::Won(_) => return moves_played - WIN_SCORE,
+        Outcome::Draw => return 0.0,
+        Outcome::InProgress => {}
+    }
+    // Forced lines need no search: a win on the next move ends the game
+    // at once, and when every move loses the opponent wins the move after.
+    if position.winning_moves() > 0 {
+        return WIN_SCORE - (moves_played + 1.0);
+    }
+    let candidates = position.non_losing_moves();
+    if candidates == 0 {
+        return moves_played + 2.0 - WIN_SCORE;
+    }
+    if depth == 0 {
+        let bound = WIN_SCORE - MAX_CELLS;
+        return search.evaluator.evaluate(position).clamp(-bound, bound);
+    }
 
-                max_eval = max_eval.max(eval_score);
-                alpha = alpha.max(eval_score);
-                if beta <= alpha {
-                    break;
-                }
+    let key = position.key();
+    let original_alpha = alpha;
+    let mut table_move = None;
+    if let Some(entry) = table.probe(key) {
+        table_move = entry.best_move;
+        if entry.depth >= depth {
+            match entry.bound {
+                Bound::Exact => return entry.score,
+                Bound::Lower => alpha = alpha.max(entry.score),
+                Bound::Upper => beta = beta.min(entry.score),
+            }
+            if alpha >= beta {
+                return entry.score;
             }
         }
-        return max_eval;
+    }
+
+    let mut moves = [(0, 0, false); MAX_COLS];
+    let count = order_moves(position, candidates, table_move, heuristics, &mut moves);
+
+    let mut best_score = f32::NEG_INFINITY;
+    let mut best_move = None;
+    for (index, &(col, cell, tactical)) in moves[..count].iter().enumerate() {
+        position.play_unchecked(col);
+        let eval_score = -negamax(position, depth - 1, -beta, -alpha, search, heuristics);
+        position.undo();
+        if search.is_stopped() {
+            return 0.0;
+        }
+
+        if eval_score > best_score {
+            best_score = eval_score;
+            best_move = Some(col);
+        }
+        alpha = alpha.max(eval_score);
+        if alpha >= beta {
+            search.record_cutoff(index == 0);
+            // Wins and blocks are found without help, so only quiet moves
+            // are remembered.
+            if !tactical {
+                heuristics.record_cutoff(position, col, cell, depth);
+            }
+            break;
+        }
+    }
+
+    let bound = if best_score <= original_alpha {
+        Bound::Upper
+    } else if best_score >= beta {
+        Bound::Lower
     } else {
-        let mut min_eval = f32::INFINITY;
-        for col in 0..COLS {
-            if can_place(game_board, col) {
-                let next_row = get_next_row(game_board, col);
-                let new_game_board = game_board | next_row;
-                let new_player_board = player_board ^ next_row;
-
-                let eval_score = minmax(
-                    new_game_board,
-                    new_player_board,
-                    bot_board,
-                    depth - 1,
-                    alpha,
-                    beta,
-                    true,
-                );
+        Bound::Exact
+    };
+    table.store(Entry {
+        key,
+        depth,
+        score: best_score,
+        bound,
+        best_move,
+    });
+    best_score
+}
 
-                min_eval = min_eval.min(eval_score);
-                beta = beta.min(eval_score);
-                if beta <= alpha {
-                    break;
+/// Searches the best column for the side to move in `position` with a
+/// fresh transposition table.
+pub fn find_best_move(position: &Position, depth: usize) -> Result<usize, EngineError> {
+    find_best_move_with_table(position, depth, &TranspositionTable::default())
+}
+
+/// Searches the best column, reusing and filling `table`. Results stay
+/// valid between searches of the same game, so keeping one table for a
+/// whole game lets later moves start from earlier work.
+pub fn find_best_move_with_table(
+    position: &Position,
+    depth: usize,
+    table: &TranspositionTable,
+) -> Result<usize, EngineError> {
+    find_best_move_with_limits(position, SearchLimits::depth(depth), table)
+}
+
+/// Searches for about `time`, deepening one ply at a time.
+pub fn find_best_move_timed(position: &Position, time: Duration) -> Result<usize, EngineError> {
+    find_best_move_with_limits(
+        position,
+        SearchLimits::time(time),
+        &TranspositionTable::default(),
+    )
+}
+
+/// Deepens one ply at a time until `limits` run out and returns the best
+/// move of the last completed iteration.
+pub fn find_best_move_with_limits(
+    position: &Position,
+    limits: SearchLimits,
+    table: &TranspositionTable,
+) -> Result<usize, EngineError> {
+    search(position, limits, table).map(|result| result.best_move)
+}
+
+/// Deepens one ply at a time until `limits` run out and reports the last
+/// completed iteration. The first iteration always completes, so a tight
+/// budget still yields a legal move. A forced win or loss ends the search
+/// early, as deeper iterations cannot change it.
+pub fn search(
+    position: &Position,
+    limits: SearchLimits,
+    table: &TranspositionTable,
+) -> Result<SearchResult, EngineError> {
+    search_with_handle(
+        position,
+        limits,
+        &EvalParams::default(),
+        table,
+        &SearchHandle::new(),
+        |_| {},
+    )
+}
+
+/// Like `search`, but evaluates with `evaluator`, stops early once
+/// `handle` is stopped and calls `on_progress` after every completed
+/// iteration. Scores in `table` depend on the evaluator, so a table should
+/// only be reused with the same one.
+pub fn search_with_handle(
+    position: &Position,
+    limits: SearchLimits,
+    evaluator: &dyn Evaluator,
+    table: &TranspositionTable,
+    handle: &SearchHandle,
+    mut on_progress: impl FnMut(&SearchResult),
+) -> Result<SearchResult, EngineError> {
+    let best = |iteration: &Iteration| {
+        let (best_move, score) = *iteration
+            .scores
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        SearchResult {
+            best_move,
+            score,
+            stats: iteration.stats,
+            moves_played: position.moves_played(),
+        }
+    };
+    let iteration = deepen(position, limits, evaluator, table, handle, |iteration| {
+        let result = best(iteration);
+        on_progress(&result);
+        !is_decisive(result.score)
+    })?;
+    Ok(best(&iteration))
+}
+
+/// Analysis of one legal column.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ColumnAnalysis {
+    pub col: usize,
+    /// Score for the side to move after playing `col`.
+    pub score: f32,
+    /// Proven result, if the search saw far enough to tell.
+    pub value: Option<Value>,
+    /// Expected continuation, starting with `col`, until the game ends or
+    /// the search depth is reached.
+    pub pv: Vec<usize>,
+    moves_played: usize,
+}
+
+impl ColumnAnalysis {
+    /// Same as `SearchResult::win_in`, counting this column as the first
+    /// move.
+    pub fn win_in(&self) -> Option<usize> {
+        win_in(self.score, self.moves_played)
+    }
+
+    /// Same as `SearchResult::loss_in`.
+    pub fn loss_in(&self) -> Option<usize> {
+        loss_in(self.score, self.moves_played)
+    }
+}
+
+/// Every legal column of a position, best first.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Analysis {
+    pub stats: SearchStats,
+    pub columns: Vec<ColumnAnalysis>,
+}
+
+impl Analysis {
+    /// The `k` best columns, or all of them if there are fewer.
+    pub fn top(&self, k: usize) -> &[ColumnAnalysis] {
+        &self.columns[..k.min(self.columns.len())]
+    }
+}
+
+/// Scores every legal column with the same iterative deepening as
+/// `search`. Deepening continues until every column is proven or `limits`
+/// run out, rather than stopping at the first forced win.
+pub fn analyse(
+    position: &Position,
+    limits: SearchLimits,
+    evaluator: &dyn Evaluator,
+    table: &TranspositionTable,
+) -> Result<Analysis, EngineError> {
+    let handle = SearchHandle::new();
+    let iteration = deepen(position, limits, evaluator, table, &handle, |iteration| {
+        !iteration
+            .scores
+            .iter()
+            .all(|&(_, score)| is_decisive(score))
+    })?;
+
+    let empty_cells = position.size().cells() - position.moves_played();
+    let mut columns: Vec<ColumnAnalysis> = iteration
+        .scores
+        .iter()
+        .map(|&(col, score)| {
+            let value = if is_decisive(score) {
+                Some(if score > 0.0 { Value::Win } else { Value::Loss })
+            } else if iteration.stats.depth >= empty_cells {
+                // Every line was followed to a full board.
+                Some(Value::Draw)
+            } else {
+                None
+            };
+            ColumnAnalysis {
+                col,
+                score,
+                value,
+                pv: principal_variation(position, col, iteration.stats.depth, evaluator, table),
+                moves_played: position.moves_played(),
+            }
+        })
+        .collect();
+    // Break ties towards the higher column, like `search`.
+    columns.sort_by(|a, b| b.score.total_cmp(&a.score).then(b.col.cmp(&a.col)));
+
+    Ok(Analysis {
+        stats: iteration.stats,
+        columns,
+    })
+}
+
+/// Follows the table's best moves from `col` for up to `depth` plies.
+fn principal_variation(
+    position: &Position,
+    col: usize,
+    depth: usize,
+    evaluator: &dyn Evaluator,
+    table: &TranspositionTable,
+) -> Vec<usize> {
+    let mut position = position.clone();
+    position.play_unchecked(col);
+    let mut pv = vec![col];
+    while pv.len() < depth && !position.is_game_over() {
+        // Only an exact entry's move is known to be best; a bound's is
+        // just the one that moved the window. Search again for those and
+        // for positions whose entries collisions have overwritten.
+        let stored = table
+            .probe(position.key())
+            .filter(|entry| entry.bound == Bound::Exact)
+            .and_then(|entry| entry.best_move)
+            .filter(|&next| position.can_play(next));
+        let next = match stored {
+            Some(next) => next,
+            None => {
+                let limits = SearchLimits::depth(depth - pv.len());
+                let handle = SearchHandle::new();
+                match search_with_handle(&position, limits, evaluator, table, &handle, |_| {}) {
+                    Ok(result) => result.best_move,
+                    Err(_) => break,
                 }
             }
+        };
+        position.play_unchecked(next);
+        pv.push(next);
+    }
+    pv
+}
+
+/// Root scores of one completed iteration.
+struct Iteration {
+    stats: SearchStats,
+    scores: Vec<(usize, f32)>,
+}
+
+/// Deepens one ply at a time until `limits` run out, `handle` is stopped
+/// or `on_iteration` returns false, and returns the last completed
+/// iteration.
+fn deepen(
+    position: &Position,
+    limits: SearchLimits,
+    evaluator: &dyn Evaluator,
+    table: &TranspositionTable,
+    handle: &SearchHandle,
+    mut on_iteration: impl FnMut(&Iteration) -> bool,
+) -> Result<Iteration, EngineError> {
+    if position.is_game_over() {
+        return Err(EngineError::GameOver);
+    }
+    if evaluator
+        .rules()
+        .is_some_and(|rules| rules != position.rules())
+    {
+        return Err(EngineError::RulesMismatch);
+    }
+
+    // Past the number of empty cells every line ends the game, so deeper
+    // iterations would only repeat the last one.
+    let empty_cells = position.size().cells() - position.moves_played();
+    let max_depth = limits.depth.unwrap_or(empty_cells).clamp(1, empty_cells);
+    let search = Search::new(evaluator, table, handle, limits);
+    // One set per root column, kept across iterations.
+    let mut heuristics: Vec<Heuristics> = (0..position.size().cols())
+        .map(|_| Heuristics::new(position.size()))
+        .collect();
+
+    let mut last = None;
+    for depth in 1..=max_depth {
+        let Some(scores) = search_root(position, depth, &search, &mut heuristics) else {
+            break;
+        };
+        let iteration = Iteration {
+            stats: search.stats(depth),
+            scores,
+        };
+        let deeper = on_iteration(&iteration);
+        last = Some(iteration);
+        if !deeper {
+            break;
         }
-        return min_eval;
+        search.interruptible.store(true, Ordering::Relaxed);
     }
+
+    let mut last = last.ok_or(EngineError::GameOver)?;
+    last.stats = search.stats(last.stats.depth);
+    Ok(last)
 }
 
-pub fn find_best_move(
-    game_board: Bitboard,
-    player_board: Bitboard,
-    bot_board: Bitboard,
+/// Scores every legal column in parallel to `depth` plies, in column
+/// order. Returns `None` when the search was stopped before it finished.
+fn search_root(
+    position: &Position,
     depth: usize,
-) -> Option<usize> {
-    let best_move = (0..COLS)
-        .into_par_iter()
-        .filter_map(|col| {
-            if can_place(game_board, col) {
-                let next_row = get_next_row(game_board, col);
-                let new_game_board = game_board | next_row;
-                let new_bot_board = bot_board ^ next_row;
-
-                let eval_score = minmax(
-                    new_game_board,
-                    player_board,
-                    new_bot_board,
+    search: &Search,
+    heuristics: &mut [Heuristics],
+) -> Option<Vec<(usize, f32)>> {
+    let scores = heuristics
+        .par_iter_mut()
+        .enumerate()
+        .filter_map(|(col, heuristics)| {
+            let mut position = position.clone();
+            if position.can_play(col) {
+                position.play_unchecked(col);
+                let eval_score = -negamax(
+                    &mut position,
                     depth - 1,
                     f32::NEG_INFINITY,
                     f32::INFINITY,
-                    false,
+                    search,
+                    heuristics,
                 );
 
                 Some((col, eval_score))
@@ -132,11 +761,242 @@ pub fn find_best_move(
                 None
             }
         })
-        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        .collect();
 
-    if let Some((col, _)) = best_move {
-        Some(col)
-    } else {
-        None
+    if search.is_stopped() {
+        return None;
+    }
+    Some(scores)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_best_move_on_wide_board() {
+        let mut position =
+            Position::with_rules(Rules::new(BoardSize::new(10, 8).unwrap(), 4).unwrap());
+        for col in [6, 6, 7, 7, 8, 8] {
+            position.play(col).unwrap();
+        }
+        let best_move = find_best_move(&position, 4);
+        assert!(best_move == Ok(5) || best_move == Ok(9));
+    }
+
+    #[test]
+    fn test_find_best_move_connect_five() {
+        let rules = Rules::new(BoardSize::new(9, 7).unwrap(), 5).unwrap();
+        let mut position = Position::with_rules(rules);
+        for col in [1, 1, 2, 2, 3, 3, 4, 4] {
+            position.play(col).unwrap();
+        }
+        let best_move = find_best_move(&position, 4);
+        assert!(best_move == Ok(0) || best_move == Ok(5));
+    }
+
+    #[test]
+    fn test_find_best_move_game_over() {
+        let mut position = Position::new();
+        for col in [0, 1, 0, 1, 0, 1, 0] {
+            position.play(col).unwrap();
+        }
+        assert_eq!(find_best_move(&position, 4), Err(EngineError::GameOver));
+    }
+
+    #[test]
+    fn test_table_is_reused_between_searches() {
+        let position = crate::notation::parse_moves(Rules::STANDARD, "4453").unwrap();
+        let table = TranspositionTable::new(4);
+        let first = find_best_move_with_table(&position, 6, &table).unwrap();
+        let hits = table.stats().hits;
+        assert!(hits > 0);
+
+        let second = find_best_move_with_table(&position, 6, &table).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, find_best_move(&position, 6).unwrap());
+        assert!(table.stats().hit_rate() > 0.0);
+    }
+
+    #[test]
+    fn test_search_limits() {
+        let position = crate::notation::parse_moves(Rules::STANDARD, "4453").unwrap();
+        let table = TranspositionTable::new(4);
+
+        // A single node still finishes the first iteration.
+        let best_move = find_best_move_with_limits(&position, SearchLimits::nodes(1), &table);
+        assert!(best_move.is_ok_and(|col| position.can_play(col)));
+
+        let start = Instant::now();
+        let best_move = find_best_move_timed(&position, Duration::from_millis(50));
+        assert!(best_move.is_ok_and(|col| position.can_play(col)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_search_limits_take_a_win() {
+        let mut position = Position::new();
+        for col in [0, 1, 0, 1, 0, 1] {
+            position.play(col).unwrap();
+        }
+        let table = TranspositionTable::default();
+        let limits = SearchLimits::time(Duration::from_millis(20));
+        assert_eq!(find_best_move_with_limits(&position, limits, &table), Ok(0));
+    }
+
+    #[test]
+    fn test_keeps_solved_win() {
+        // The solver proves this endgame is won for the side to move; the
+        // bot's move must keep it that way.
+        let mut position =
+            crate::notation::parse_moves(Rules::STANDARD, "7422341735647741166133573473242566")
+                .unwrap();
+        let best_move = find_best_move(&position, 8).unwrap();
+        position.play(best_move).unwrap();
+        let solution = crate::solver::solve(&position).unwrap();
+        assert_eq!(solution.value(), crate::solver::Value::Loss);
+    }
+
+    #[test]
+    fn test_order_moves() {
+        // First to move wins in columns 2 and 6 and must block column 0.
+        let position = crate::notation::parse_moves(Rules::STANDARD, "415161").unwrap();
+        let mut heuristics = Heuristics::new(position.size());
+        let mut moves = [(0, 0, false); MAX_COLS];
+        let playable = position.size().playable(position.mask());
+        let count = order_moves(&position, playable, None, &heuristics, &mut moves);
+        let order: Vec<usize> = moves[..count].iter().map(|m| m.0).collect();
+        assert_eq!(order, [2, 6, 0, 3, 4, 1, 5]);
+
+        let cell = position.size().next_row(position.mask(), 5);
+        heuristics.record_cutoff(&position, 5, cell, 4);
+        let count = order_moves(&position, playable, Some(4), &heuristics, &mut moves);
+        let order: Vec<usize> = moves[..count].iter().map(|m| m.0).collect();
+        assert_eq!(order, [2, 6, 0, 4, 5, 3, 1]);
+        assert!(moves[2].2 && !moves[3].2);
+    }
+
+    #[test]
+    fn test_search_reports_distance() {
+        let table = TranspositionTable::default();
+        let limits = SearchLimits::depth(6);
+
+        let position = crate::notation::parse_moves(Rules::STANDARD, "121212").unwrap();
+        let result = search(&position, limits, &table).unwrap();
+        assert_eq!(result.best_move, 0);
+        assert_eq!(result.win_in(), Some(1));
+        assert_eq!(result.score, WIN_SCORE - 7.0);
+
+        // An open three on the bottom row wins on the next move, not later.
+        let position = crate::notation::parse_moves(Rules::STANDARD, "4455").unwrap();
+        let result = search(&position, limits, &table).unwrap();
+        assert!(result.best_move == 2 || result.best_move == 5);
+        assert_eq!(result.win_in(), Some(2));
+        assert_eq!(result.loss_in(), None);
+
+        let position = crate::notation::parse_moves(Rules::STANDARD, "44556").unwrap();
+        let result = search(&position, limits, &table).unwrap();
+        assert_eq!(result.loss_in(), Some(1));
+        assert_eq!(result.win_in(), None);
+
+        // Huge loaded weights still never look like a proven result.
+        let params = EvalParams::parse("connect_two = 1e9").unwrap();
+        let position = crate::notation::parse_moves(Rules::STANDARD, "4453").unwrap();
+        let result = search_with_handle(
+            &position,
+            SearchLimits::depth(1),
+            &params,
+            &TranspositionTable::default(),
+            &SearchHandle::new(),
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(result.score.abs(), WIN_SCORE - MAX_CELLS);
+        assert_eq!(result.win_in(), None);
+        assert_eq!(result.loss_in(), None);
+
+        let quiet = search(&Position::new(), SearchLimits::depth(4), &table).unwrap();
+        assert!(!is_decisive(quiet.score));
+        assert_eq!(quiet.stats.depth, 4);
+    }
+
+    #[test]
+    fn test_search_handle_and_progress() {
+        let table = TranspositionTable::default();
+        let mut depths = Vec::new();
+        let result = search_with_handle(
+            &Position::new(),
+            SearchLimits::depth(5),
+            &EvalParams::default(),
+            &table,
+            &SearchHandle::new(),
+            |progress| depths.push(progress.stats.depth),
+        )
+        .unwrap();
+        assert_eq!(depths, [1, 2, 3, 4, 5]);
+        assert!(result.stats.nodes > 0);
+        assert!(result.stats.cutoffs > 0);
+        assert!(result.stats.first_move_cutoff_rate() > 0.5);
+        assert!(result.stats.table_hits <= result.stats.table_probes);
+
+        // A stopped handle still lets the first iteration finish.
+        let handle = SearchHandle::new();
+        let clone = handle.clone();
+        clone.stop();
+        assert!(handle.is_stopped());
+        let result = search_with_handle(
+            &Position::new(),
+            SearchLimits::default(),
+            &EvalParams::default(),
+            &table,
+            &handle,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(result.stats.depth, 1);
+    }
+
+    #[test]
+    fn test_analyse() {
+        let params = EvalParams::default();
+        let table = TranspositionTable::default();
+        let position = crate::notation::parse_moves(Rules::STANDARD, "4455").unwrap();
+        let analysis = analyse(&position, SearchLimits::depth(6), &params, &table).unwrap();
+        assert_eq!(analysis.columns.len(), 7);
+        assert_eq!(analysis.top(2).len(), 2);
+        assert_eq!(analysis.top(10).len(), 7);
+
+        let best = &analysis.columns[0];
+        assert_eq!(best.value, Some(Value::Win));
+        assert_eq!(best.win_in(), Some(2));
+        assert_eq!(best.col, best.pv[0]);
+        let mut line = position.clone();
+        for &col in &best.pv {
+            line.play(col).unwrap();
+        }
+        assert_eq!(line.outcome(), Outcome::Won(Player::First));
+
+        // Four empty cells: every line is searched to the end.
+        let position =
+            crate::notation::parse_moves(Rules::STANDARD, "23163416124767223154467471272416755633")
+                .unwrap();
+        let analysis = analyse(&position, SearchLimits::default(), &params, &table).unwrap();
+        assert_eq!(analysis.columns[0].value, Some(Value::Draw));
+        assert!(analysis.columns.iter().all(|column| column.value.is_some()));
+
+        // A fail-low entry's move is not followed.
+        let position = crate::notation::parse_moves(Rules::STANDARD, "41516").unwrap();
+        let mut child = position.clone();
+        child.play_unchecked(0);
+        let table = TranspositionTable::default();
+        table.store(Entry {
+            key: child.key(),
+            depth: 4,
+            score: 0.0,
+            bound: Bound::Upper,
+            best_move: Some(0),
+        });
+        let pv = principal_variation(&position, 0, 2, &params, &table);
+        assert!(pv == [0, 2] || pv == [0, 6]);
     }
 }
@@ -1,24 +1,30 @@
+use crate::agent::Agent;
 use crate::bitboard::*;
 use crate::bot::*;
+use crate::eval::{EvalParams, Evaluator};
+use crate::notation::format_moves;
+use crate::solver::Value;
+use crate::transposition::TranspositionTable;
 use std::io;
 use std::process;
 
 const PLAYER1_SYMBOL: &str = "\x1b[32mO\x1b[0m";
 const PLAYER2_SYMBOL: &str = "\x1b[31mO\x1b[0m";
+const HINT_LIMITS: SearchLimits = SearchLimits {
+    depth: Some(12),
+    time: None,
+    nodes: None,
+};
 
-pub fn print_board(player1_board: Bitboard, player2_board: Bitboard) {
-    for row in (0..6).rev() {
-        for col in 0..COLS {
-            let shift = row + (col * COLS);
-            let player1_bit = (player1_board >> shift) & 1;
-            let player2_bit = (player2_board >> shift) & 1;
-
-            if player1_bit == 1 {
-                print!("{} ", PLAYER1_SYMBOL);
-            } else if player2_bit == 1 {
-                print!("{} ", PLAYER2_SYMBOL);
-            } else {
-                print!("O ");
+/// Prints `position` with the human player's stones in PLAYER1_SYMBOL.
+pub fn print_board(position: &Position, player: Player) {
+    let size = position.size();
+    for row in (0..size.rows()).rev() {
+        for col in 0..size.cols() {
+            match position.cell(row, col) {
+                Some(owner) if owner == player => print!("{} ", PLAYER1_SYMBOL),
+                Some(_) => print!("{} ", PLAYER2_SYMBOL),
+                None => print!("O "),
             }
         }
         println!();
@@ -26,79 +32,125 @@ pub fn print_board(player1_board: Bitboard, player2_board: Bitboard) {
 
     println!();
 
-    for col in 0..COLS {
-        print!("{} ", col);
+    for col in 0..size.cols() {
+        print!("{} ", col + 1);
     }
     println!();
 }
 
-pub fn player_vs_bot(depth: usize) {
-    let mut game_board: Bitboard = 0;
-    let mut player_board: Bitboard = 0;
-    let mut bot_board: Bitboard = 0;
-    let mut player_turn: bool = true;
+/// Prints the three best columns for the side to move with their expected
+/// continuation.
+pub fn print_hint(position: &Position, limits: SearchLimits, evaluator: &dyn Evaluator) {
+    let analysis = match analyse(position, limits, evaluator, &TranspositionTable::default()) {
+        Ok(analysis) => analysis,
+        Err(error) => {
+            println!("Failed to analyse the position: {}.", error);
+            return;
+        }
+    };
+    for column in analysis.top(3) {
+        let verdict = match (column.win_in(), column.loss_in(), column.value) {
+            (Some(moves), _, _) => format!("wins in {}", moves),
+            (_, Some(moves), _) => format!("loses in {}", moves),
+            (_, _, Some(Value::Draw)) => "draw".to_string(),
+            _ => format!("{:+.2}", column.score),
+        };
+        let line: Vec<String> = column.pv.iter().map(|col| (col + 1).to_string()).collect();
+        println!("{}: {} ({})", column.col + 1, verdict, line.join(" "));
+    }
+}
+
+/// Plays the column the player typed, counting from 1 like the board's
+/// labels, or returns the message to show instead.
+fn play_input(position: &mut Position, input: &str) -> Result<(), String> {
+    let col = match input.trim().parse::<usize>() {
+        Ok(col) if col > 0 => col - 1,
+        _ => {
+            return Err(format!(
+                "Please enter a column from 1 to {}.",
+                position.size().cols()
+            ));
+        }
+    };
+    position
+        .play(col)
+        .map_err(|error| format!("Invalid move: {}.", error))
+}
+
+/// Plays against `agent` in the terminal, e.g. one made by
+/// `agent::create_agent("minimax:2s")`.
+pub fn player_vs_bot(rules: Rules, agent: &mut dyn Agent) {
+    let mut player = Player::First;
+    let mut position = Position::with_rules(rules);
     let mut game_over: bool = false;
 
     loop {
         if !game_over {
-            if player_turn {
-                print_board(player_board, bot_board);
-                println!("Enter your move: ");
+            if position.side_to_move() == player {
+                print_board(&position, player);
+                println!("Enter your move (h for a hint): ");
                 let mut player_input: String = String::new();
                 io::stdin()
                     .read_line(&mut player_input)
                     .expect("Failed to read line");
 
-                let player_input: usize = match player_input.trim().parse() {
-                    Ok(num) => num,
-                    Err(_) => {
-                        println!("Please enter a number.");
-                        continue;
-                    }
-                };
-
-                if can_place(game_board, player_input) {
-                    let next_row = get_next_row(game_board, player_input);
-                    game_board |= next_row;
-                    player_board ^= next_row;
-                    player_turn = false;
-                } else {
-                    println!("Invalid move.");
+                if player_input.trim() == "h" {
+                    print_hint(&position, HINT_LIMITS, &EvalParams::default());
+                    continue;
+                }
+
+                if let Err(message) = play_input(&mut position, &player_input) {
+                    println!("{}", message);
                     continue;
                 }
             } else {
-                let best_move = find_best_move(game_board, player_board, bot_board, depth);
-                if best_move != None {
-                    let best_move: usize = best_move.unwrap();
-                    let next_row = get_next_row(game_board, best_move);
-                    game_board |= next_row;
-                    bot_board ^= next_row;
-                    println!("Bot move: {}", best_move);
-                    player_turn = true;
-                } else {
-                    println!("Failed to find the best move.");
-                    process::exit(1);
+                // Only searching agents report progress, and only they
+                // can see a forced win coming.
+                let mut last = None;
+                let handle = SearchHandle::new();
+                match agent
+                    .choose_move_with_handle(&position, &handle, &mut |result| last = Some(*result))
+                    .and_then(|col| {
+                        position.play(col)?;
+                        Ok(col)
+                    }) {
+                    Ok(col) => {
+                        println!("Bot move: {}", col + 1);
+                        let win_in = last.and_then(|result: SearchResult| result.win_in());
+                        if let Some(moves) = win_in.filter(|&moves| moves > 1) {
+                            println!("Bot wins in {} moves.", moves);
+                        }
+                    }
+                    Err(error) => {
+                        println!("Failed to find the best move: {}.", error);
+                        game_over = true;
+                        continue;
+                    }
                 }
             }
 
-            if has_won(player_board) {
-                print_board(player_board, bot_board);
-                println!("Player won!");
-                player_turn = false;
-                game_over = true;
-            }
-
-            if has_won(bot_board) {
-                print_board(player_board, bot_board);
-                println!("Bot won!");
-                player_turn = true;
-                game_over = true;
+            match position.outcome() {
+                Outcome::Won(winner) => {
+                    print_board(&position, player);
+                    if winner == player {
+                        println!("Player won!");
+                        player = Player::Second;
+                    } else {
+                        println!("Bot won!");
+                        player = Player::First;
+                    }
+                    game_over = true;
+                }
+                Outcome::Draw => {
+                    print_board(&position, player);
+                    println!("Draw");
+                    game_over = true;
+                }
+                Outcome::InProgress => {}
             }
 
-            if is_board_full(game_board) {
-                print_board(player_board, bot_board);
-                println!("Draw");
-                game_over = true;
+            if game_over {
+                println!("Moves: {}", format_moves(&position));
             }
         } else {
             println!("Enter r to replay or q to quit:");
@@ -116,9 +168,8 @@ pub fn player_vs_bot(depth: usize) {
             };
 
             if player_input == "r" {
-                game_board = 0;
-                player_board = 0;
-                bot_board = 0;
+                position = Position::with_rules(rules);
+                agent.new_game();
                 game_over = false;
             } else if player_input == "q" {
                 process::exit(1);
@@ -126,3 +177,28 @@ pub fn player_vs_bot(depth: usize) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+
+    #[test]
+    fn test_play_input() {
+        let mut position = parse_moves(Rules::STANDARD, "111111").unwrap();
+        assert_eq!(
+            play_input(&mut position, "1\n"),
+            Err("Invalid move: column 1 is full.".to_string())
+        );
+        assert_eq!(
+            play_input(&mut position, "8"),
+            Err("Invalid move: column 8 is outside a 7 column board.".to_string())
+        );
+        assert_eq!(
+            play_input(&mut position, "0"),
+            Err("Please enter a column from 1 to 7.".to_string())
+        );
+        assert_eq!(play_input(&mut position, "4\n"), Ok(()));
+        assert_eq!(position.moves().last(), Some(&3));
+    }
+}
@@ -0,0 +1,37 @@
+use std::error::Error;
+use std::fmt;
+
+/// Columns are 0-based in the variants and shown 1-based, as in move
+/// strings.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EngineError {
+    ColumnOutOfRange { col: usize, cols: usize },
+    ColumnFull { col: usize },
+    GameOver,
+    OverlappingBoards,
+    UnreachablePosition,
+    RulesMismatch,
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::ColumnOutOfRange { col, cols } => {
+                write!(f, "column {} is outside a {} column board", col + 1, cols)
+            }
+            EngineError::ColumnFull { col } => write!(f, "column {} is full", col + 1),
+            EngineError::GameOver => write!(f, "the game is already over"),
+            EngineError::OverlappingBoards => {
+                write!(f, "both players have a stone on the same cell")
+            }
+            EngineError::UnreachablePosition => {
+                write!(f, "the position cannot be reached by legal play")
+            }
+            EngineError::RulesMismatch => {
+                write!(f, "the position is played under different rules")
+            }
+        }
+    }
+}
+
+impl Error for EngineError {}
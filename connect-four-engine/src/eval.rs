@@ -0,0 +1,404 @@
+use crate::bitboard::*;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Scores positions where the search stops, from the point of view of the
+/// side to move.
+///
+/// Evaluations must stay far below `bot::WIN_SCORE`, or the search will
+/// mistake them for forced wins.
+pub trait Evaluator: Send + Sync {
+    fn evaluate(&self, position: &Position) -> f32;
+
+    /// The only rules the evaluator understands, if it is tied to one
+    /// board. Searches reject positions under any other rules.
+    fn rules(&self) -> Option<Rules> {
+        None
+    }
+}
+
+/// Weights of the heuristic evaluation at the search horizon. Lines are
+/// named after standard rules: under connect-N, `connect_three` weighs
+/// lines one stone short of a win and `connect_two` lines two short.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EvalParams {
+    /// Score per stone by distance from the middle column, or from the two
+    /// middle columns on an even-width board. Columns past the end of the
+    /// list score nothing.
+    pub centre: Vec<f32>,
+    /// Score per possible winning line holding two of a player's stones
+    /// and none of the opponent's.
+    pub connect_two: f32,
+    pub connect_three: f32,
+    /// Bonus per empty cell extending a row of two adjacent stones at
+    /// either end.
+    pub open_two: f32,
+    pub open_three: f32,
+    /// Score per empty cell that would complete a line and can be played
+    /// at once.
+    pub immediate_threat: f32,
+    /// Score per empty cell that would complete a line once the cells
+    /// below it are filled.
+    pub threat: f32,
+    /// Added to `threat` for cells on the rows that favour their owner:
+    /// odd rows, counted from one at the bottom, for the first player and
+    /// even rows for the second. Filling the board tends to leave each
+    /// player the threats of their own parity.
+    pub parity_threat: f32,
+}
+
+impl EvalParams {
+    /// Reads parameters from a file of `key = value` lines. See `parse`.
+    pub fn load(path: impl AsRef<Path>) -> Result<EvalParams, EvalParamsError> {
+        EvalParams::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parses `key = value` lines, with `#` starting a comment. Keys are the
+    /// field names; `centre` takes a comma-separated list. Missing keys
+    /// keep their default, so a file only needs the weights it changes.
+    pub fn parse(text: &str) -> Result<EvalParams, EvalParamsError> {
+        let mut params = EvalParams::default();
+        for (line, content) in text.lines().enumerate() {
+            let content = content.split('#').next().unwrap_or_default().trim();
+            if content.is_empty() {
+                continue;
+            }
+            let (key, value) = content
+                .split_once('=')
+                .ok_or(EvalParamsError::MissingValue { line })?;
+            let (key, value) = (key.trim(), value.trim());
+            let number = |value: &str| {
+                value
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|number| number.is_finite())
+                    .ok_or_else(|| EvalParamsError::InvalidValue {
+                        line,
+                        value: value.trim().to_string(),
+                    })
+            };
+            match key {
+                "centre" => {
+                    params.centre = value.split(',').map(number).collect::<Result<_, _>>()?
+                }
+                "connect_two" => params.connect_two = number(value)?,
+                "connect_three" => params.connect_three = number(value)?,
+                "open_two" => params.open_two = number(value)?,
+                "open_three" => params.open_three = number(value)?,
+                "immediate_threat" => params.immediate_threat = number(value)?,
+                "threat" => params.threat = number(value)?,
+                "parity_threat" => params.parity_threat = number(value)?,
+                _ => {
+                    return Err(EvalParamsError::UnknownKey {
+                        line,
+                        key: key.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(params)
+    }
+
+    /// Scores `position` from the point of view of the side to move.
+    pub fn evaluate(&self, position: &Position) -> f32 {
+        let rules = position.rules();
+        let size = rules.size();
+        let mask = position.mask();
+        let current = position.current();
+        let opponent = position.opponent();
+
+        let mut total_score = 0.0;
+        for col in 0..size.cols() {
+            // Twice the distance from the middle is odd between the two
+            // middle columns of an even-width board, so halving it puts
+            // both at distance zero.
+            let distance = (2 * col).abs_diff(size.cols() - 1) / 2;
+            if let Some(weight) = self.centre.get(distance) {
+                let col_mask = size.col_mask(col);
+                let stones = (current & col_mask).count_ones() as f32
+                    - (opponent & col_mask).count_ones() as f32;
+                total_score += stones * weight;
+            }
+        }
+
+        total_score += self.lines(rules, current, opponent);
+
+        let empty = size.board_mask() & !mask;
+        total_score += self.open_ends(rules, current, empty);
+        total_score -= self.open_ends(rules, opponent, empty);
+        let playable = size.playable(mask);
+        let odd_rows = size.bottom_row_mask()
+            * (0..size.rows())
+                .step_by(2)
+                .fold(0, |rows: Bitboard, row| rows | 1 << row);
+        let (current_rows, opponent_rows) = match position.side_to_move() {
+            Player::First => (odd_rows, !odd_rows),
+            Player::Second => (!odd_rows, odd_rows),
+        };
+        total_score += self.threats(rules.winning_cells(current) & empty, playable, current_rows);
+        total_score -= self.threats(
+            rules.winning_cells(opponent) & empty,
+            playable,
+            opponent_rows,
+        );
+        total_score
+    }
+
+    /// Scores every run of `connect` cells that only one player has stones
+    /// in. Runs holding stones of both players can never win, so they are
+    /// ignored however many stones they hold.
+    fn lines(&self, rules: Rules, current: Bitboard, opponent: Bitboard) -> f32 {
+        let connect = rules.connect();
+        let weight = |stones: u32| match connect - stones as usize {
+            1 => self.connect_three,
+            2 => self.connect_two,
+            _ => 0.0,
+        };
+
+        let mut total_score = 0.0;
+        for direction in rules.size().directions() {
+            // Each bit of `ends` is the last cell of a run that fits on the
+            // board, so its first cell is `connect - 1` steps back.
+            let mut ends = rules.lines(rules.size().board_mask(), direction, connect);
+            if ends == 0 {
+                continue;
+            }
+            let run = (0..connect).fold(0, |run: Bitboard, i| run | 1 << (i * direction));
+            while ends > 0 {
+                let end = ends.trailing_zeros() as usize;
+                ends &= ends - 1;
+                let cells = run << (end - (connect - 1) * direction);
+                let mine = (current & cells).count_ones();
+                let theirs = (opponent & cells).count_ones();
+                if mine > 0 && theirs == 0 {
+                    total_score += weight(mine);
+                } else if theirs > 0 && mine == 0 {
+                    total_score -= weight(theirs);
+                }
+            }
+        }
+        total_score
+    }
+
+    /// Scores the empty cells at either end of rows of adjacent stones one
+    /// and two short of a win.
+    fn open_ends(&self, rules: Rules, bitboard: Bitboard, empty: Bitboard) -> f32 {
+        let connect = rules.connect();
+        let mut total_score = 0.0;
+        for (missing, weight) in [(2, self.open_two), (1, self.open_three)] {
+            let length = connect - missing;
+            if weight == 0.0 || length < 2 {
+                continue;
+            }
+            for direction in rules.size().directions() {
+                // A line's bit marks its last stone, so the cells past
+                // either end are one step after it and `length` steps
+                // before it.
+                let line = rules.lines(bitboard, direction, length);
+                let after = line.checked_shl(direction as u32).unwrap_or(0);
+                let before = line.checked_shr((length * direction) as u32).unwrap_or(0);
+                total_score +=
+                    ((after & empty).count_ones() + (before & empty).count_ones()) as f32 * weight;
+            }
+        }
+        total_score
+    }
+
+    fn threats(&self, threats: Bitboard, playable: Bitboard, good_rows: Bitboard) -> f32 {
+        let immediate = threats & playable;
+        let future = threats & !playable;
+        immediate.count_ones() as f32 * self.immediate_threat
+            + future.count_ones() as f32 * self.threat
+            + (future & good_rows).count_ones() as f32 * self.parity_threat
+    }
+}
+
+impl Default for EvalParams {
+    fn default() -> EvalParams {
+        EvalParams {
+            centre: vec![0.25],
+            connect_two: 1.0,
+            connect_three: 4.0,
+            open_two: 0.0,
+            open_three: 0.0,
+            immediate_threat: 8.0,
+            threat: 4.0,
+            parity_threat: 4.0,
+        }
+    }
+}
+
+impl Evaluator for EvalParams {
+    fn evaluate(&self, position: &Position) -> f32 {
+        EvalParams::evaluate(self, position)
+    }
+}
+
+/// Writes the parameters in the format `EvalParams::parse` reads.
+impl fmt::Display for EvalParams {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let centre: Vec<String> = self
+            .centre
+            .iter()
+            .map(|weight| weight.to_string())
+            .collect();
+        writeln!(f, "centre = {}", centre.join(", "))?;
+        writeln!(f, "connect_two = {}", self.connect_two)?;
+        writeln!(f, "connect_three = {}", self.connect_three)?;
+        writeln!(f, "open_two = {}", self.open_two)?;
+        writeln!(f, "open_three = {}", self.open_three)?;
+        writeln!(f, "immediate_threat = {}", self.immediate_threat)?;
+        writeln!(f, "threat = {}", self.threat)?;
+        writeln!(f, "parity_threat = {}", self.parity_threat)
+    }
+}
+
+#[derive(Debug)]
+pub enum EvalParamsError {
+    Io(io::Error),
+    MissingValue { line: usize },
+    UnknownKey { line: usize, key: String },
+    InvalidValue { line: usize, value: String },
+}
+
+impl fmt::Display for EvalParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalParamsError::Io(error) => write!(f, "{}", error),
+            EvalParamsError::MissingValue { line } => {
+                write!(f, "line {}: expected 'key = value'", line + 1)
+            }
+            EvalParamsError::UnknownKey { line, key } => {
+                write!(f, "line {}: unknown parameter '{}'", line + 1, key)
+            }
+            EvalParamsError::InvalidValue { line, value } => {
+                write!(f, "line {}: '{}' is not a number", line + 1, value)
+            }
+        }
+    }
+}
+
+impl Error for EvalParamsError {}
+
+impl From<io::Error> for EvalParamsError {
+    fn from(error: io::Error) -> EvalParamsError {
+        EvalParamsError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+
+    const ZERO: EvalParams = EvalParams {
+        centre: Vec::new(),
+        connect_two: 0.0,
+        connect_three: 0.0,
+        open_two: 0.0,
+        open_three: 0.0,
+        immediate_threat: 0.0,
+        threat: 0.0,
+        parity_threat: 0.0,
+    };
+
+    #[test]
+    fn test_lines_ignore_blocked_runs() {
+        assert_eq!(EvalParams::default().evaluate(&Position::new()), 0.0);
+
+        let params = EvalParams {
+            connect_two: 1.0,
+            ..ZERO
+        };
+        // First's bottom-row pair lies in three open runs of four.
+        let position = parse_moves(Rules::STANDARD, "445").unwrap();
+        assert_eq!(params.evaluate(&position), -3.0);
+        // Second's stone on the right leaves only the run to the left.
+        let position = parse_moves(Rules::STANDARD, "4456").unwrap();
+        assert_eq!(params.evaluate(&position), 1.0);
+    }
+
+    #[test]
+    fn test_open_ends() {
+        let params = EvalParams {
+            open_two: 0.5,
+            open_three: 1.0,
+            ..ZERO
+        };
+        // First's bottom-row pair is open at both ends.
+        let position = parse_moves(Rules::STANDARD, "445").unwrap();
+        assert_eq!(params.evaluate(&position), -1.0);
+        // First's three is open at both ends, and so is Second's pair
+        // above it.
+        let position = parse_moves(Rules::STANDARD, "44556").unwrap();
+        assert_eq!(params.evaluate(&position), 1.0 - 2.0 - 0.5 * 2.0);
+    }
+
+    #[test]
+    fn test_threats() {
+        let params = EvalParams {
+            immediate_threat: 8.0,
+            threat: 4.0,
+            parity_threat: 2.0,
+            ..ZERO
+        };
+        // Second, to move, can complete the bottom row at once. First's
+        // threat on the second row needs the cell below filled first, and
+        // even rows do not favour First.
+        let position = parse_moves(Rules::STANDARD, "7112233").unwrap();
+        assert_eq!(params.evaluate(&position), 8.0 - 4.0);
+
+        // First's threat on the third row is on an odd row, so it scores
+        // the parity bonus too.
+        let position = parse_moves(Rules::STANDARD, "211332271736").unwrap();
+        assert_eq!(params.evaluate(&position), 4.0 + 2.0);
+    }
+
+    #[test]
+    fn test_centre_weights_on_even_board() {
+        let rules = Rules::new(BoardSize::new(8, 6).unwrap(), 4).unwrap();
+        let params = EvalParams {
+            centre: vec![1.0, 0.5],
+            connect_two: 1.0,
+            ..ZERO
+        };
+        let mut position = Position::with_rules(rules);
+        for col in [3, 2] {
+            position.play(col).unwrap();
+        }
+        assert_eq!(params.evaluate(&position), 0.5);
+        position.play(4).unwrap();
+        assert_eq!(params.evaluate(&position), 0.5 - 2.0 - 1.0);
+    }
+
+    #[test]
+    fn test_parse() {
+        let params = EvalParams::parse(
+            "# aggressive\ncentre = 0.5, 0.25\n\nconnect_three = 12 # more\nopen_two=0.5\nthreat=0.5\n",
+        )
+        .unwrap();
+        assert_eq!(params.centre, [0.5, 0.25]);
+        assert_eq!(params.connect_two, 1.0);
+        assert_eq!(params.connect_three, 12.0);
+        assert_eq!(params.open_two, 0.5);
+        assert_eq!(params.threat, 0.5);
+        assert_eq!(EvalParams::parse(&params.to_string()).unwrap(), params);
+
+        assert!(matches!(
+            EvalParams::parse("centre"),
+            Err(EvalParamsError::MissingValue { line: 0 })
+        ));
+        assert!(matches!(
+            EvalParams::parse("\nmiddle = 1"),
+            Err(EvalParamsError::UnknownKey { line: 1, .. })
+        ));
+        assert!(matches!(
+            EvalParams::parse("centre = 1, x"),
+            Err(EvalParamsError::InvalidValue { line: 0, .. })
+        ));
+    }
+}
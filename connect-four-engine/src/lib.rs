@@ -1,3 +1,19 @@
+pub mod agent;
+pub mod arena;
+pub mod bench;
 pub mod bitboard;
+pub mod book;
 pub mod bot;
 pub mod cli_game;
+pub mod error;
+pub mod eval;
+pub mod mcts;
+pub mod network;
+pub mod notation;
+pub mod ntuple;
+pub mod puct;
+pub mod rng;
+pub mod selfplay;
+pub mod solver;
+pub mod transposition;
+pub mod tune;
@@ -0,0 +1,533 @@
+use connect_four_engine::agent::{Agent, MinimaxAgent, create_agent};
+use connect_four_engine::arena::*;
+use connect_four_engine::bench::*;
+use connect_four_engine::bitboard::{Outcome, Player, Rules};
+use connect_four_engine::book::*;
+use connect_four_engine::bot::{SearchLimits, SearchStats};
+use connect_four_engine::cli_game;
+use connect_four_engine::eval::EvalParams;
+use connect_four_engine::network::{DEFAULT_HIDDEN, Network};
+use connect_four_engine::notation::{format_moves, parse_moves};
+use connect_four_engine::ntuple::*;
+use connect_four_engine::puct::PuctParams;
+use connect_four_engine::rng::Rng;
+use connect_four_engine::selfplay::{Trainer, TrainingParams};
+use connect_four_engine::tune::*;
+use std::env;
+use std::fs;
+use std::process;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+usage: connect-four-engine play [--bot AGENT | [--depth N | --time SECONDS] [--eval FILE | --ntuple FILE]]
+       connect-four-engine bench [--depth N] [TEST_SET...]
+       connect-four-engine train [--iterations N] [--games N] [--simulations N] [--resume FILE] DIR
+       connect-four-engine td [--games N] [--resume FILE] FILE
+       connect-four-engine label [--games N] FILE
+       connect-four-engine tune [--eval FILE] [--iterations N] TEST_SET...
+       connect-four-engine arena [--games N] [--gauntlet] [--sprt ELO0,ELO1]
+                                 [--plies N] [--window SCORE] AGENT...
+       connect-four-engine openings [--plies N] [--depth N] [--window SCORE]
+       connect-four-engine book [--plies N] [--depth N] [--window SCORE] FILE";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("play") => play(&args[1..]),
+        Some("bench") => bench(&args[1..]),
+        Some("train") => train(&args[1..]),
+        Some("td") => td(&args[1..]),
+        Some("label") => label(&args[1..]),
+        Some("tune") => tune_eval(&args[1..]),
+        Some("arena") => arena(&args[1..]),
+        Some("openings") => list_openings(&args[1..]),
+        Some("book") => book(&args[1..]),
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+struct Options {
+    depth: Option<usize>,
+    time: Option<f64>,
+    eval: Option<String>,
+    ntuple: Option<String>,
+    bot: Option<String>,
+    iterations: Option<usize>,
+    games: Option<usize>,
+    simulations: Option<usize>,
+    resume: Option<String>,
+    gauntlet: bool,
+    sprt: Option<(f64, f64)>,
+    plies: Option<usize>,
+    window: Option<f32>,
+    files: Vec<String>,
+}
+
+/// Parses the flags of a subcommand, exiting with the usage message on
+/// any flag not in `allowed`.
+fn parse_options(args: &[String], allowed: &[&str]) -> Options {
+    let mut options = Options {
+        depth: None,
+        time: None,
+        eval: None,
+        ntuple: None,
+        bot: None,
+        iterations: None,
+        games: None,
+        simulations: None,
+        resume: None,
+        gauntlet: false,
+        sprt: None,
+        plies: None,
+        window: None,
+        files: Vec::new(),
+    };
+    let mut args = args.iter();
+    let count = |args: &mut std::slice::Iter<String>| {
+        Some(
+            args.next()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or_else(|| usage()),
+        )
+    };
+    while let Some(arg) = args.next() {
+        if arg.starts_with("--") && !allowed.contains(&arg.as_str()) {
+            usage();
+        }
+        match arg.as_str() {
+            "--depth" => options.depth = count(&mut args),
+            "--iterations" => options.iterations = count(&mut args),
+            "--games" => options.games = count(&mut args),
+            "--simulations" => options.simulations = count(&mut args),
+            "--plies" => options.plies = count(&mut args),
+            "--window" => {
+                options.window = Some(
+                    args.next()
+                        .and_then(|s| s.parse().ok())
+                        .filter(|window: &f32| *window >= 0.0)
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--time" => {
+                options.time = Some(
+                    args.next()
+                        .and_then(|s| s.parse().ok())
+                        .filter(|time: &f64| time.is_finite() && *time > 0.0)
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            "--eval" => options.eval = Some(args.next().cloned().unwrap_or_else(|| usage())),
+            "--ntuple" => options.ntuple = Some(args.next().cloned().unwrap_or_else(|| usage())),
+            "--bot" => options.bot = Some(args.next().cloned().unwrap_or_else(|| usage())),
+            "--resume" => options.resume = Some(args.next().cloned().unwrap_or_else(|| usage())),
+            "--gauntlet" => options.gauntlet = true,
+            "--sprt" => {
+                options.sprt = Some(
+                    args.next()
+                        .and_then(|s| s.split_once(','))
+                        .and_then(|(elo0, elo1)| Some((elo0.parse().ok()?, elo1.parse().ok()?)))
+                        .unwrap_or_else(|| usage()),
+                )
+            }
+            flag if flag.starts_with("--") => usage(),
+            file => options.files.push(file.to_string()),
+        }
+    }
+    options
+}
+
+fn play(args: &[String]) {
+    let options = parse_options(args, &["--bot", "--depth", "--time", "--eval", "--ntuple"]);
+    if !options.files.is_empty() {
+        usage();
+    }
+    let mut agent: Box<dyn Agent> = match &options.bot {
+        Some(_)
+            if options.depth.is_some()
+                || options.time.is_some()
+                || options.eval.is_some()
+                || options.ntuple.is_some() =>
+        {
+            usage()
+        }
+        Some(spec) => create_agent(spec).unwrap_or_else(|error| {
+            eprintln!("{}", error);
+            process::exit(1);
+        }),
+        None => {
+            let limits = match (options.depth, options.time) {
+                (Some(depth), None) => SearchLimits::depth(depth),
+                (None, Some(seconds)) => SearchLimits::time(Duration::from_secs_f64(seconds)),
+                (None, None) => SearchLimits::depth(8),
+                (Some(_), Some(_)) => usage(),
+            };
+            match (&options.eval, &options.ntuple) {
+                (Some(file), None) => {
+                    let params = EvalParams::load(file).unwrap_or_else(|error| {
+                        eprintln!("{}: {}", file, error);
+                        process::exit(1);
+                    });
+                    Box::new(MinimaxAgent::new(limits, params))
+                }
+                (None, Some(file)) => {
+                    let network = NTupleNetwork::load(file).unwrap_or_else(|error| {
+                        eprintln!("{}: {}", file, error);
+                        process::exit(1);
+                    });
+                    if network.rules() != Rules::STANDARD {
+                        eprintln!("{}: the network was trained under different rules", file);
+                        process::exit(1);
+                    }
+                    Box::new(MinimaxAgent::new(limits, network))
+                }
+                (None, None) => Box::new(MinimaxAgent::new(limits, EvalParams::default())),
+                (Some(_), Some(_)) => usage(),
+            }
+        }
+    };
+    cli_game::player_vs_bot(Rules::STANDARD, agent.as_mut());
+}
+
+fn bench(args: &[String]) {
+    let options = parse_options(args, &["--depth"]);
+    let depth = options.depth.unwrap_or(BENCH_DEPTH);
+
+    println!("search to depth {}", depth);
+    let positions: Vec<_> = BENCH_POSITIONS
+        .iter()
+        .map(|moves| parse_moves(Rules::STANDARD, moves).unwrap())
+        .collect();
+    let mut total = SearchStats::default();
+    for (moves, stats) in BENCH_POSITIONS.iter().zip(bench_search(&positions, depth)) {
+        print_stats(if moves.is_empty() { "(start)" } else { moves }, &stats);
+        total += stats;
+    }
+    print_stats("total", &total);
+
+    println!();
+    if options.files.is_empty() {
+        report_solver("built-in", &parse_test_set(BENCH_TEST_SET).unwrap());
+    }
+    for file in &options.files {
+        match read_test_set(file) {
+            Ok(cases) => report_solver(file, &cases),
+            Err(error) => {
+                eprintln!("{}: {}", file, error);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn train(args: &[String]) {
+    let options = parse_options(
+        args,
+        &["--iterations", "--games", "--simulations", "--resume"],
+    );
+    let [dir] = options.files.as_slice() else {
+        usage()
+    };
+
+    let mut rng = Rng::default();
+    let network = match &options.resume {
+        Some(file) => Network::load(file).unwrap_or_else(|error| {
+            eprintln!("{}: {}", file, error);
+            process::exit(1);
+        }),
+        None => Network::new(Rules::STANDARD, &DEFAULT_HIDDEN, &mut rng),
+    };
+    let mut params = TrainingParams::default();
+    if let Some(games) = options.games {
+        params.games = games;
+    }
+    if let Some(simulations) = options.simulations {
+        params.search = PuctParams::simulations(simulations);
+    }
+
+    let mut trainer = Trainer::new(network, params, rng);
+    let result = trainer.train(options.iterations.unwrap_or(10), dir, |stats| {
+        println!(
+            "iteration {:>4}: first {:>3} second {:>3} draws {:>3}  {:>6} samples  policy loss {:.3} value loss {:.3}  {:.1} s",
+            stats.iteration,
+            stats.first_wins,
+            stats.second_wins,
+            stats.draws,
+            stats.samples,
+            stats.loss.policy,
+            stats.loss.value,
+            stats.elapsed.as_secs_f64(),
+        );
+    });
+    if let Err(error) = result {
+        eprintln!("{}: {}", dir, error);
+        process::exit(1);
+    }
+}
+
+fn td(args: &[String]) {
+    let options = parse_options(args, &["--games", "--resume"]);
+    let [file] = options.files.as_slice() else {
+        usage()
+    };
+
+    let mut rng = Rng::default();
+    let mut network = match &options.resume {
+        Some(resume) => NTupleNetwork::load(resume).unwrap_or_else(|error| {
+            eprintln!("{}: {}", resume, error);
+            process::exit(1);
+        }),
+        None => NTupleNetwork::new(
+            Rules::STANDARD,
+            DEFAULT_TUPLES,
+            DEFAULT_TUPLE_LENGTH,
+            &mut rng,
+        ),
+    };
+    let params = TdParams::default();
+    let games = options.games.unwrap_or(100_000);
+    // Report and save ten times over the run.
+    let interval = games.div_ceil(10).max(1);
+    let started = Instant::now();
+    let mut results = [0; 3];
+    for game in 1..=games {
+        results[match network.train_game(&params, &mut rng) {
+            Outcome::Won(Player::First) => 0,
+            Outcome::Won(Player::Second) => 1,
+            _ => 2,
+        }] += 1;
+        if game % interval == 0 || game == games {
+            println!(
+                "games {:>8}: first {:>6} second {:>6} draws {:>6}  {:.1} s",
+                game,
+                results[0],
+                results[1],
+                results[2],
+                started.elapsed().as_secs_f64(),
+            );
+            results = [0; 3];
+            if let Err(error) = network.save(file) {
+                eprintln!("{}: {}", file, error);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn label(args: &[String]) {
+    let options = parse_options(args, &["--games"]);
+    let [file] = options.files.as_slice() else {
+        usage()
+    };
+
+    let games = options.games.unwrap_or(5000);
+    let started = Instant::now();
+    let cases = generate_positions(games, MIN_STONES, &mut Rng::default());
+    if let Err(error) = fs::write(file, format_test_set(&cases)) {
+        eprintln!("{}: {}", file, error);
+        process::exit(1);
+    }
+    println!(
+        "solved {} positions in {:.1} s",
+        cases.len(),
+        started.elapsed().as_secs_f64()
+    );
+}
+
+/// Prints the tuned parameters to standard output and progress to
+/// standard error, so the output can be redirected to a file.
+fn tune_eval(args: &[String]) {
+    let options = parse_options(args, &["--eval", "--iterations"]);
+    if options.files.is_empty() {
+        usage();
+    }
+
+    let start = match &options.eval {
+        Some(file) => EvalParams::load(file).unwrap_or_else(|error| {
+            eprintln!("{}: {}", file, error);
+            process::exit(1);
+        }),
+        None => EvalParams::default(),
+    };
+    let mut cases = Vec::new();
+    for file in &options.files {
+        match read_test_set(file) {
+            Ok(set) => cases.extend(set),
+            Err(error) => {
+                eprintln!("{}: {}", file, error);
+                process::exit(1);
+            }
+        }
+    }
+    let mut params = TuneParams::default();
+    if let Some(iterations) = options.iterations {
+        params.iterations = iterations;
+    }
+
+    let interval = params.iterations.div_ceil(10).max(1);
+    let result = tune(&cases, &start, &params, |iteration, error| {
+        if iteration % interval == 0 {
+            eprintln!("iteration {:>6}: error {:.6}", iteration, error);
+        }
+    });
+    eprintln!(
+        "{} positions, scale {:.4}, error {:.6} -> {:.6}",
+        cases.len(),
+        result.scale,
+        result.initial_error,
+        result.error
+    );
+    print!("{}", result.params);
+}
+
+fn arena(args: &[String]) {
+    let options = parse_options(
+        args,
+        &["--games", "--gauntlet", "--sprt", "--plies", "--window"],
+    );
+    if options.files.len() < 2 {
+        usage();
+    }
+
+    let mut params = ArenaParams {
+        schedule: if options.gauntlet {
+            Schedule::Gauntlet
+        } else {
+            Schedule::RoundRobin
+        },
+        sprt: options.sprt.map(|(elo0, elo1)| SprtParams::new(elo0, elo1)),
+        ..ArenaParams::default()
+    };
+    if let Some(games) = options.games {
+        params.games = games;
+    }
+    if let Some(plies) = options.plies {
+        params.opening_plies = plies;
+    }
+    if let Some(window) = options.window {
+        params.opening_window = Some(window);
+    }
+
+    let started = Instant::now();
+    let results = run_arena(&options.files, &params, |result| {
+        let sprt = match result.sprt {
+            Some(SprtResult::AcceptH0) => "  sprt: H0 accepted",
+            Some(SprtResult::AcceptH1) => "  sprt: H1 accepted",
+            Some(SprtResult::Continue) => "  sprt: inconclusive",
+            None => "",
+        };
+        println!(
+            "{} vs {}: +{} ={} -{}  elo {:+.0} +/- {:.0}{}  {:.1} s",
+            result.first,
+            result.second,
+            result.wins,
+            result.draws,
+            result.losses,
+            result.elo(),
+            result.elo_error(),
+            sprt,
+            started.elapsed().as_secs_f64(),
+        );
+    })
+    .unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    });
+
+    // Points and games per agent over all its matches.
+    let mut standings: Vec<(&String, f64, usize)> =
+        options.files.iter().map(|spec| (spec, 0.0, 0)).collect();
+    for result in &results {
+        for (spec, points, games) in &mut standings {
+            if **spec == result.first {
+                *points += result.wins as f64 + result.draws as f64 / 2.0;
+                *games += result.games();
+            } else if **spec == result.second {
+                *points += result.losses as f64 + result.draws as f64 / 2.0;
+                *games += result.games();
+            }
+        }
+    }
+    standings.sort_by(|a, b| (b.1 / b.2.max(1) as f64).total_cmp(&(a.1 / a.2.max(1) as f64)));
+    println!();
+    for (spec, points, games) in standings {
+        println!("{:<30} {:>7.1} / {:<5}", spec, points, games);
+    }
+}
+
+/// Prints the openings of `--plies` moves that search to within
+/// `--window` of even, one move string per line.
+fn list_openings(args: &[String]) {
+    let options = parse_options(args, &["--plies", "--depth", "--window"]);
+    if !options.files.is_empty() {
+        usage();
+    }
+
+    let openings = balanced_openings(
+        Rules::STANDARD,
+        options.plies.unwrap_or(4),
+        options.depth.unwrap_or(BOOK_DEPTH),
+        options.window.unwrap_or(BOOK_WINDOW),
+    );
+    for position in &openings {
+        println!("{}", format_moves(position));
+    }
+}
+
+fn book(args: &[String]) {
+    let options = parse_options(args, &["--plies", "--depth", "--window"]);
+    let [file] = options.files.as_slice() else {
+        usage()
+    };
+
+    let started = Instant::now();
+    let book = OpeningBook::generate(
+        Rules::STANDARD,
+        options.plies.unwrap_or(8),
+        options.depth.unwrap_or(BOOK_DEPTH),
+        options.window.unwrap_or(BOOK_WINDOW),
+    );
+    if let Err(error) = book.save(file) {
+        eprintln!("{}: {}", file, error);
+        process::exit(1);
+    }
+    println!(
+        "{} positions in {:.1} s",
+        book.len(),
+        started.elapsed().as_secs_f64()
+    );
+}
+
+fn print_stats(name: &str, stats: &SearchStats) {
+    println!(
+        "{:<38} depth {:>2} {:>11} nodes {:>9.1} ms {:>8.0} knps  cutoffs {:>5.1}% first {:>5.1}%  tt hits {:>5.1}%",
+        name,
+        stats.depth,
+        stats.nodes,
+        stats.elapsed.as_secs_f64() * 1000.0,
+        stats.nodes_per_second() / 1000.0,
+        stats.cutoff_rate() * 100.0,
+        stats.first_move_cutoff_rate() * 100.0,
+        stats.table_hit_rate() * 100.0,
+    );
+}
+
+fn report_solver(name: &str, cases: &[TestCase]) {
+    let bench = bench_solver(cases);
+    println!(
+        "{}: {}/{} correct, mean {:.3} ms, mean {:.0} nodes, {:.0} knps",
+        name,
+        bench.correct(),
+        bench.cases,
+        bench.mean_time().as_secs_f64() * 1000.0,
+        bench.mean_nodes(),
+        bench.nodes_per_second() / 1000.0,
+    );
+    for (case, score) in &bench.failures {
+        println!("  {} expected {} got {}", case.moves, case.score, score);
+    }
+}
@@ -0,0 +1,388 @@
+use crate::bitboard::*;
+use crate::bot::SearchHandle;
+use crate::error::EngineError;
+use crate::rng::Rng;
+use rayon::prelude::*;
+use std::f64::consts::SQRT_2;
+use std::time::{Duration, Instant};
+
+/// How playouts pick their moves.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Rollout {
+    /// Any legal column with equal chance.
+    Random,
+    /// Wins when it can, otherwise avoids moves that lose at once and
+    /// picks among the rest at random.
+    #[default]
+    Guided,
+}
+
+/// Budget and tuning of a Monte Carlo tree search. Unset budgets are
+/// unbounded, so with neither set the search runs until its handle is
+/// stopped. Small budgets make natural-looking mistakes, which suits
+/// beginners better than a shallow alpha-beta search.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MctsParams {
+    /// Playouts in total, shared between the threads.
+    pub iterations: Option<u64>,
+    pub time: Option<Duration>,
+    /// UCT exploration constant. Higher values spend more playouts on
+    /// moves that look worse so far.
+    pub exploration: f64,
+    pub rollout: Rollout,
+    /// Trees grown in parallel, their root statistics merged at the end.
+    pub threads: usize,
+}
+
+impl MctsParams {
+    pub fn iterations(iterations: u64) -> MctsParams {
+        MctsParams {
+            iterations: Some(iterations),
+            ..MctsParams::default()
+        }
+    }
+
+    pub fn time(time: Duration) -> MctsParams {
+        MctsParams {
+            iterations: None,
+            time: Some(time),
+            ..MctsParams::default()
+        }
+    }
+}
+
+impl Default for MctsParams {
+    fn default() -> MctsParams {
+        MctsParams {
+            iterations: Some(10_000),
+            time: None,
+            exploration: SQRT_2,
+            rollout: Rollout::default(),
+            threads: rayon::current_num_threads(),
+        }
+    }
+}
+
+/// Outcome of a search from the side to move's point of view.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MctsResult {
+    pub best_move: usize,
+    /// Share of the playouts through `best_move` won by the side to move,
+    /// counting draws as half.
+    pub win_rate: f64,
+    pub iterations: u64,
+    pub elapsed: Duration,
+    /// Playouts through each legal column, in column order.
+    pub visits: Vec<(usize, u64)>,
+}
+
+/// Searches with a fresh set of trees and returns the most played column.
+pub fn mcts(
+    position: &Position,
+    params: &MctsParams,
+    rng: &mut Rng,
+) -> Result<MctsResult, EngineError> {
+    mcts_with_handle(position, params, rng, &SearchHandle::new())
+}
+
+/// Like `mcts`, but stops early once `handle` is stopped. Every tree
+/// completes at least one playout, so there is always a move.
+pub fn mcts_with_handle(
+    position: &Position,
+    params: &MctsParams,
+    rng: &mut Rng,
+    handle: &SearchHandle,
+) -> Result<MctsResult, EngineError> {
+    if position.is_game_over() {
+        return Err(EngineError::GameOver);
+    }
+    let started = Instant::now();
+    let deadline = params.time.map(|time| started + time);
+    let threads = match params.iterations {
+        Some(iterations) => params.threads.clamp(1, iterations.max(1) as usize),
+        None => params.threads.max(1),
+    };
+    let seeds: Vec<u64> = (0..threads).map(|_| rng.next_u64()).collect();
+
+    let trees: Vec<Tree> = seeds
+        .into_par_iter()
+        .enumerate()
+        .map(|(thread, seed)| {
+            // Spread the playouts evenly, the first threads taking any
+            // remainder.
+            let budget = params.iterations.map(|iterations| {
+                iterations / threads as u64
+                    + u64::from((thread as u64) < iterations % threads as u64)
+            });
+            let mut tree = Tree::new(position);
+            tree.grow(params, budget, deadline, handle, &mut Rng::new(seed));
+            tree
+        })
+        .collect();
+
+    // Merge the root statistics of every tree by column.
+    let cols = position.size().cols();
+    let mut visits = vec![0; cols];
+    let mut rewards = vec![0.0; cols];
+    let mut iterations = 0;
+    for tree in &trees {
+        iterations += u64::from(tree.nodes[0].visits);
+        for &child in &tree.nodes[0].children {
+            let node = &tree.nodes[child as usize];
+            visits[node.col] += u64::from(node.visits);
+            rewards[node.col] += node.reward;
+        }
+    }
+    let best_move = (0..cols)
+        .filter(|&col| visits[col] > 0)
+        .max_by(|&a, &b| {
+            visits[a]
+                .cmp(&visits[b])
+                .then((rewards[a] / visits[a] as f64).total_cmp(&(rewards[b] / visits[b] as f64)))
+        })
+        .unwrap();
+
+    Ok(MctsResult {
+        best_move,
+        win_rate: rewards[best_move] / visits[best_move] as f64,
+        iterations,
+        elapsed: started.elapsed(),
+        visits: (0..cols)
+            .filter(|&col| position.can_play(col))
+            .map(|col| (col, visits[col]))
+            .collect(),
+    })
+}
+
+// The clock is only read every this many playouts.
+const TIME_CHECK_INTERVAL: u64 = 64;
+
+struct Node {
+    /// Column played to reach this node from its parent.
+    col: usize,
+    /// The player who played `col`.
+    mover: Player,
+    children: Vec<u32>,
+    /// Playable cells without a child yet. Empty once the game is over.
+    untried: Bitboard,
+    visits: u32,
+    /// Playouts through this node won by `mover`, draws counting half.
+    reward: f64,
+}
+
+struct Tree {
+    root: Position,
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    fn new(position: &Position) -> Tree {
+        let root = Node {
+            col: usize::MAX,
+            mover: position.side_to_move().opponent(),
+            children: Vec::new(),
+            untried: position.size().playable(position.mask()),
+            visits: 0,
+            reward: 0.0,
+        };
+        Tree {
+            root: position.clone(),
+            nodes: vec![root],
+        }
+    }
+
+    fn grow(
+        &mut self,
+        params: &MctsParams,
+        budget: Option<u64>,
+        deadline: Option<Instant>,
+        handle: &SearchHandle,
+        rng: &mut Rng,
+    ) {
+        let mut path = Vec::new();
+        let mut iterations: u64 = 0;
+        loop {
+            self.iterate(params, rng, &mut path);
+            iterations += 1;
+
+            let out_of_iterations = budget.is_some_and(|budget| iterations >= budget);
+            let out_of_time = iterations.is_multiple_of(TIME_CHECK_INTERVAL)
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if out_of_iterations || out_of_time || handle.is_stopped() {
+                break;
+            }
+        }
+    }
+
+    /// Runs one playout: selects down the tree by UCT, adds one node,
+    /// plays the game out and credits every node on the way.
+    fn iterate(&mut self, params: &MctsParams, rng: &mut Rng, path: &mut Vec<u32>) {
+        let mut position = self.root.clone();
+        let mut index = 0;
+        path.clear();
+        path.push(0);
+
+        while self.nodes[index].untried == 0 && !self.nodes[index].children.is_empty() {
+            index = self.select(index, params.exploration);
+            position.play_unchecked(self.nodes[index].col);
+            path.push(index as u32);
+        }
+
+        let untried = self.nodes[index].untried;
+        if untried > 0 {
+            let cell = random_cell(untried, rng);
+            self.nodes[index].untried ^= cell;
+            let col = cell.trailing_zeros() as usize / position.size().stride();
+            let mover = position.side_to_move();
+            position.play_unchecked(col);
+            let untried = if position.is_game_over() {
+                0
+            } else {
+                position.size().playable(position.mask())
+            };
+            let child = self.nodes.len() as u32;
+            self.nodes.push(Node {
+                col,
+                mover,
+                children: Vec::new(),
+                untried,
+                visits: 0,
+                reward: 0.0,
+            });
+            self.nodes[index].children.push(child);
+            path.push(child);
+        }
+
+        let winner = playout(&mut position, params.rollout, rng);
+        for &index in path.iter() {
+            let node = &mut self.nodes[index as usize];
+            node.visits += 1;
+            node.reward += match winner {
+                Some(winner) if winner == node.mover => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+        }
+    }
+
+    /// The child of `parent` with the highest upper confidence bound.
+    fn select(&self, parent: usize, exploration: f64) -> usize {
+        let log_visits = f64::from(self.nodes[parent].visits).ln();
+        let bound = |&child: &u32| {
+            let node = &self.nodes[child as usize];
+            let visits = f64::from(node.visits);
+            node.reward / visits + exploration * (log_visits / visits).sqrt()
+        };
+        self.nodes[parent]
+            .children
+            .iter()
+            .max_by(|a, b| bound(a).total_cmp(&bound(b)))
+            .map(|&child| child as usize)
+            .unwrap()
+    }
+}
+
+/// Plays `position` to the end and returns the winner, if any.
+fn playout(position: &mut Position, rollout: Rollout, rng: &mut Rng) -> Option<Player> {
+    match position.outcome() {
+        Outcome::Won(winner) => return Some(winner),
+        Outcome::Draw => return None,
+        Outcome::InProgress => {}
+    }
+    let rules = position.rules();
+    let size = rules.size();
+    loop {
+        let side = position.side_to_move();
+        let moves = match rollout {
+            Rollout::Random => size.playable(position.mask()),
+            Rollout::Guided => {
+                if position.winning_moves() > 0 {
+                    return Some(side);
+                }
+                match position.non_losing_moves() {
+                    // The opponent wins whatever is played.
+                    0 => return Some(side.opponent()),
+                    moves => moves,
+                }
+            }
+        };
+        let col = random_cell(moves, rng).trailing_zeros() as usize / size.stride();
+        position.play_unchecked(col);
+        if rules.has_won(position.board(side)) {
+            return Some(side);
+        }
+        if size.is_board_full(position.mask()) {
+            return None;
+        }
+    }
+}
+
+/// One set cell of `cells`, which must not be empty, with equal chance.
+fn random_cell(cells: Bitboard, rng: &mut Rng) -> Bitboard {
+    let mut remaining = cells;
+    for _ in 0..rng.below(cells.count_ones() as usize) {
+        remaining &= remaining - 1;
+    }
+    remaining & remaining.wrapping_neg()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+
+    #[test]
+    fn test_mcts_finds_tactics() {
+        let mut rng = Rng::new(1);
+        let params = MctsParams::iterations(2000);
+        // First wins in column 4 rather than blocking column 5.
+        let position = parse_moves(Rules::STANDARD, "454545").unwrap();
+        let result = mcts(&position, &params, &mut rng).unwrap();
+        assert_eq!(result.best_move, 3);
+        assert!(result.win_rate > 0.9);
+
+        // Second must block column 4.
+        let position = parse_moves(Rules::STANDARD, "11223").unwrap();
+        let result = mcts(&position, &params, &mut rng).unwrap();
+        assert_eq!(result.best_move, 3);
+    }
+
+    #[test]
+    fn test_mcts_budgets() {
+        let position = Position::new();
+        let params = MctsParams {
+            rollout: Rollout::Random,
+            threads: 3,
+            ..MctsParams::iterations(100)
+        };
+        let result = mcts(&position, &params, &mut Rng::new(2)).unwrap();
+        assert_eq!(result.iterations, 100);
+        assert_eq!(result.visits.len(), 7);
+        assert_eq!(result.visits.iter().map(|v| v.1).sum::<u64>(), 100);
+
+        // Single-threaded searches repeat exactly from the same seed.
+        let params = MctsParams {
+            threads: 1,
+            ..MctsParams::iterations(500)
+        };
+        let a = mcts(&position, &params, &mut Rng::new(5)).unwrap();
+        let b = mcts(&position, &params, &mut Rng::new(5)).unwrap();
+        assert_eq!(a.visits, b.visits);
+
+        // A stopped handle still completes one playout per tree.
+        let handle = SearchHandle::new();
+        handle.stop();
+        let params = MctsParams {
+            threads: 2,
+            ..MctsParams::time(Duration::from_secs(60))
+        };
+        let result = mcts_with_handle(&position, &params, &mut Rng::new(3), &handle).unwrap();
+        assert_eq!(result.iterations, 2);
+
+        let over = parse_moves(Rules::STANDARD, "4545454").unwrap();
+        assert_eq!(
+            mcts(&over, &params, &mut Rng::new(4)),
+            Err(EngineError::GameOver)
+        );
+    }
+}
@@ -0,0 +1,495 @@
+use crate::bitboard::*;
+use crate::rng::Rng;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Hidden layer sizes of a new network unless told otherwise. Small enough
+/// to train on a CPU in minutes.
+pub const DEFAULT_HIDDEN: [usize; 2] = [64, 64];
+
+const MAGIC: &[u8; 4] = b"C4NN";
+const VERSION: u32 = 1;
+// Guards against allocating gigabytes for a corrupt header: 64 MB of
+// weights, far beyond anything worth training on a CPU.
+const MAX_PARAMETERS: usize = 1 << 24;
+
+/// A small fully connected policy/value network. It reads the stones of
+/// the side to move and of the opponent, and predicts a probability for
+/// each column and the expected result, from -1 for a loss to 1 for a win.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Network {
+    rules: Rules,
+    /// ReLU layers shared by both heads.
+    trunk: Vec<Layer>,
+    policy: Layer,
+    value: Layer,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Layer {
+    inputs: usize,
+    outputs: usize,
+    /// Row-major, one row of `inputs` weights per output.
+    weights: Vec<f32>,
+    biases: Vec<f32>,
+}
+
+/// What a network expects for one position.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Prediction {
+    /// Probability of each column, zero for full ones.
+    pub policy: Vec<f32>,
+    /// Expected result for the side to move.
+    pub value: f32,
+}
+
+/// A training target: an encoded position with the move probabilities and
+/// result the network should predict for it.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Sample {
+    pub input: Vec<f32>,
+    pub policy: Vec<f32>,
+    pub value: f32,
+}
+
+/// Mean losses over a training batch.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Loss {
+    /// Cross-entropy between the predicted and target policies.
+    pub policy: f32,
+    /// Squared error of the predicted value.
+    pub value: f32,
+}
+
+/// One input per cell and player, with the side to move's stones first.
+/// Cells are ordered column by column from the bottom up.
+pub fn encode(position: &Position) -> Vec<f32> {
+    let size = position.size();
+    let cells = size.cols() * size.rows();
+    let mut input = vec![0.0; 2 * cells];
+    for (offset, mut board) in [(0, position.current()), (cells, position.opponent())] {
+        while board > 0 {
+            let bit = board.trailing_zeros() as usize;
+            board &= board - 1;
+            let (col, row) = (bit / size.stride(), bit % size.stride());
+            input[offset + col * size.rows() + row] = 1.0;
+        }
+    }
+    input
+}
+
+impl Network {
+    /// A randomly initialised network for `rules` with the given hidden
+    /// layer sizes.
+    pub fn new(rules: Rules, hidden: &[usize], rng: &mut Rng) -> Network {
+        let size = rules.size();
+        let mut inputs = 2 * size.cols() * size.rows();
+        let mut trunk = Vec::new();
+        for &outputs in hidden {
+            trunk.push(Layer::new(inputs, outputs, rng));
+            inputs = outputs;
+        }
+        Network {
+            rules,
+            trunk,
+            policy: Layer::new(inputs, size.cols(), rng),
+            value: Layer::new(inputs, 1, rng),
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    pub fn hidden(&self) -> Vec<usize> {
+        self.trunk.iter().map(|layer| layer.outputs).collect()
+    }
+
+    /// Predicts the policy over the playable columns of `position` and its
+    /// value for the side to move.
+    pub fn evaluate(&self, position: &Position) -> Prediction {
+        let (logits, value) = self.forward(&encode(position), &mut Vec::new());
+        let size = position.size();
+        let max = (0..size.cols())
+            .filter(|&col| position.can_play(col))
+            .map(|col| logits[col])
+            .fold(f32::NEG_INFINITY, f32::max);
+        let mut policy: Vec<f32> = (0..size.cols())
+            .map(|col| {
+                if position.can_play(col) {
+                    (logits[col] - max).exp()
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+        let total: f32 = policy.iter().sum();
+        if total > 0.0 {
+            policy.iter_mut().for_each(|p| *p /= total);
+        }
+        Prediction { policy, value }
+    }
+
+    /// Runs the layers on `input`, keeping every trunk activation in
+    /// `activations` for backpropagation. Returns the policy logits and the
+    /// value.
+    fn forward(&self, input: &[f32], activations: &mut Vec<Vec<f32>>) -> (Vec<f32>, f32) {
+        activations.clear();
+        activations.push(input.to_vec());
+        for layer in &self.trunk {
+            let mut output = layer.forward(activations.last().unwrap());
+            output.iter_mut().for_each(|x| *x = x.max(0.0));
+            activations.push(output);
+        }
+        let hidden = activations.last().unwrap();
+        let logits = self.policy.forward(hidden);
+        let value = self.value.forward(hidden)[0].tanh();
+        (logits, value)
+    }
+
+    /// Takes one gradient descent step on `samples`, minimising the policy
+    /// cross-entropy plus the squared value error, with L2 weight decay.
+    /// Returns the losses before the step.
+    pub fn train(&mut self, samples: &[Sample], learning_rate: f32, weight_decay: f32) -> Loss {
+        let mut trunk: Vec<Layer> = self.trunk.iter().map(Layer::zeroed).collect();
+        let mut policy = self.policy.zeroed();
+        let mut value = self.value.zeroed();
+        let mut loss = Loss::default();
+        let mut activations = Vec::new();
+
+        for sample in samples {
+            let (logits, predicted) = self.forward(&sample.input, &mut activations);
+
+            // Softmax cross-entropy: the gradient is the predicted minus the
+            // target probabilities.
+            let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let exps: Vec<f32> = logits.iter().map(|&z| (z - max).exp()).collect();
+            let total: f32 = exps.iter().sum();
+            let mut policy_delta = vec![0.0; logits.len()];
+            for (col, &target) in sample.policy.iter().enumerate() {
+                let p = exps[col] / total;
+                policy_delta[col] = p - target;
+                if target > 0.0 {
+                    loss.policy -= target * p.max(f32::MIN_POSITIVE).ln();
+                }
+            }
+            let error = predicted - sample.value;
+            loss.value += error * error;
+            let value_delta = [2.0 * error * (1.0 - predicted * predicted)];
+
+            let hidden = activations.last().unwrap();
+            let mut delta = vec![0.0; hidden.len()];
+            self.policy
+                .backward(hidden, &policy_delta, &mut policy, &mut delta);
+            self.value
+                .backward(hidden, &value_delta, &mut value, &mut delta);
+            for (i, layer) in self.trunk.iter().enumerate().rev() {
+                // ReLU passes gradients only where it was active.
+                for (d, &a) in delta.iter_mut().zip(&activations[i + 1]) {
+                    if a <= 0.0 {
+                        *d = 0.0;
+                    }
+                }
+                let mut input_delta = vec![0.0; layer.inputs];
+                layer.backward(&activations[i], &delta, &mut trunk[i], &mut input_delta);
+                delta = input_delta;
+            }
+        }
+
+        let scale = 1.0 / samples.len().max(1) as f32;
+        for (layer, gradient) in self.trunk.iter_mut().zip(&trunk) {
+            layer.step(
+                gradient,
+                learning_rate * scale,
+                learning_rate * weight_decay,
+            );
+        }
+        self.policy
+            .step(&policy, learning_rate * scale, learning_rate * weight_decay);
+        self.value
+            .step(&value, learning_rate * scale, learning_rate * weight_decay);
+        Loss {
+            policy: loss.policy * scale,
+            value: loss.value * scale,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Network, NetworkError> {
+        Network::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), NetworkError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads the format written by `write_to`: a header with the rules and
+    /// layer sizes, then every weight and bias as a little-endian `f32`.
+    pub fn read_from(mut reader: impl Read) -> Result<Network, NetworkError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(NetworkError::NotANetwork);
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(NetworkError::UnsupportedVersion { version });
+        }
+        let cols = read_u32(&mut reader)? as usize;
+        let rows = read_u32(&mut reader)? as usize;
+        let connect = read_u32(&mut reader)? as usize;
+        let rules = BoardSize::new(cols, rows)
+            .and_then(|size| Rules::new(size, connect))
+            .ok_or(NetworkError::InvalidShape)?;
+        let layers = read_u32(&mut reader)?;
+        let size = rules.size();
+        let mut inputs = 2 * size.cols() * size.rows();
+        let mut parameters = 0;
+        let mut hidden = Vec::new();
+        for _ in 0..layers {
+            let outputs = read_u32(&mut reader)? as usize;
+            parameters += (inputs + 1) * outputs;
+            if outputs == 0 || parameters > MAX_PARAMETERS {
+                return Err(NetworkError::InvalidShape);
+            }
+            hidden.push(outputs);
+            inputs = outputs;
+        }
+        if parameters + (inputs + 1) * (size.cols() + 1) > MAX_PARAMETERS {
+            return Err(NetworkError::InvalidShape);
+        }
+
+        let mut network = Network::new(rules, &hidden, &mut Rng::new(0));
+        let Network {
+            trunk,
+            policy,
+            value,
+            ..
+        } = &mut network;
+        for layer in trunk.iter_mut().chain([policy, value]) {
+            for weight in layer.weights.iter_mut().chain(layer.biases.iter_mut()) {
+                *weight = read_f32(&mut reader)?;
+            }
+        }
+        if reader.read(&mut [0])? > 0 {
+            return Err(NetworkError::InvalidShape);
+        }
+        Ok(network)
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let size = self.rules.size();
+        writer.write_all(MAGIC)?;
+        for value in [
+            VERSION,
+            size.cols() as u32,
+            size.rows() as u32,
+            self.rules.connect() as u32,
+            self.trunk.len() as u32,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for layer in &self.trunk {
+            writer.write_all(&(layer.outputs as u32).to_le_bytes())?;
+        }
+        for layer in self.trunk.iter().chain([&self.policy, &self.value]) {
+            for weight in layer.weights.iter().chain(&layer.biases) {
+                writer.write_all(&weight.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Layer {
+    /// He-uniform weights, which keep ReLU activations at a steady scale
+    /// through the layers.
+    fn new(inputs: usize, outputs: usize, rng: &mut Rng) -> Layer {
+        let limit = (6.0 / inputs as f64).sqrt();
+        Layer {
+            inputs,
+            outputs,
+            weights: (0..inputs * outputs)
+                .map(|_| ((rng.next_f64() * 2.0 - 1.0) * limit) as f32)
+                .collect(),
+            biases: vec![0.0; outputs],
+        }
+    }
+
+    fn zeroed(&self) -> Layer {
+        Layer {
+            inputs: self.inputs,
+            outputs: self.outputs,
+            weights: vec![0.0; self.weights.len()],
+            biases: vec![0.0; self.biases.len()],
+        }
+    }
+
+    fn forward(&self, input: &[f32]) -> Vec<f32> {
+        self.weights
+            .chunks_exact(self.inputs)
+            .zip(&self.biases)
+            .map(|(row, bias)| bias + row.iter().zip(input).map(|(w, x)| w * x).sum::<f32>())
+            .collect()
+    }
+
+    /// Adds the gradients for one sample to `gradient` and the gradient
+    /// with respect to the input to `input_delta`.
+    fn backward(
+        &self,
+        input: &[f32],
+        delta: &[f32],
+        gradient: &mut Layer,
+        input_delta: &mut [f32],
+    ) {
+        for (output, &d) in delta.iter().enumerate() {
+            if d == 0.0 {
+                continue;
+            }
+            let start = output * self.inputs;
+            let row = &self.weights[start..start + self.inputs];
+            let gradient_row = &mut gradient.weights[start..start + self.inputs];
+            for i in 0..self.inputs {
+                gradient_row[i] += d * input[i];
+                input_delta[i] += d * row[i];
+            }
+            gradient.biases[output] += d;
+        }
+    }
+
+    fn step(&mut self, gradient: &Layer, rate: f32, decay: f32) {
+        for (weight, g) in self.weights.iter_mut().zip(&gradient.weights) {
+            *weight -= rate * g + decay * *weight;
+        }
+        for (bias, g) in self.biases.iter_mut().zip(&gradient.biases) {
+            *bias -= rate * g;
+        }
+    }
+}
+
+pub(crate) fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub(crate) fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Io(io::Error),
+    NotANetwork,
+    UnsupportedVersion { version: u32 },
+    InvalidShape,
+    InvalidWeight,
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Io(error) => write!(f, "{}", error),
+            NetworkError::NotANetwork => write!(f, "not a network file"),
+            NetworkError::UnsupportedVersion { version } => {
+                write!(f, "unsupported network file version {}", version)
+            }
+            NetworkError::InvalidShape => write!(f, "invalid network layout"),
+            NetworkError::InvalidWeight => write!(f, "network weight is not a finite number"),
+        }
+    }
+}
+
+impl Error for NetworkError {}
+
+impl From<io::Error> for NetworkError {
+    fn from(error: io::Error) -> NetworkError {
+        NetworkError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+
+    #[test]
+    fn test_encode_and_evaluate() {
+        let position = parse_moves(Rules::STANDARD, "4441111117").unwrap();
+        let input = encode(&position);
+        assert_eq!(input.len(), 84);
+        // First is to move and owns the bottom of column 4 and column 1.
+        assert_eq!(input[3 * 6], 1.0);
+        assert_eq!(input[42 + 3 * 6 + 1], 1.0);
+        assert_eq!(input.iter().sum::<f32>(), 10.0);
+
+        let network = Network::new(Rules::STANDARD, &[16], &mut Rng::new(1));
+        let prediction = network.evaluate(&position);
+        assert_eq!(prediction.policy[0], 0.0);
+        assert!((prediction.policy.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(prediction.value.abs() <= 1.0);
+    }
+
+    #[test]
+    fn test_training_fits_targets() {
+        let mut network = Network::new(Rules::STANDARD, &[32], &mut Rng::new(2));
+        let position = parse_moves(Rules::STANDARD, "44").unwrap();
+        let mut policy = vec![0.0; 7];
+        policy[2] = 1.0;
+        let samples = [Sample {
+            input: encode(&position),
+            policy,
+            value: 0.5,
+        }];
+        let first = network.train(&samples, 0.05, 0.0);
+        for _ in 0..200 {
+            network.train(&samples, 0.05, 0.0);
+        }
+        let last = network.train(&samples, 0.05, 0.0);
+        assert!(last.policy < first.policy / 4.0);
+        assert!(last.value < 0.01);
+        let prediction = network.evaluate(&position);
+        assert!(prediction.policy[2] > 0.8);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let rules = Rules::new(BoardSize::new(5, 4).unwrap(), 3).unwrap();
+        let network = Network::new(rules, &[8, 4], &mut Rng::new(3));
+        let mut bytes = Vec::new();
+        network.write_to(&mut bytes).unwrap();
+        assert_eq!(Network::read_from(bytes.as_slice()).unwrap(), network);
+
+        assert!(matches!(
+            Network::read_from(&bytes[..bytes.len() - 1]),
+            Err(NetworkError::Io(_))
+        ));
+        bytes.push(0);
+        assert!(matches!(
+            Network::read_from(bytes.as_slice()),
+            Err(NetworkError::InvalidShape)
+        ));
+        assert!(matches!(
+            Network::read_from(&b"C4XX"[..]),
+            Err(NetworkError::NotANetwork)
+        ));
+
+        // A header asking for more weights than any network should have is
+        // rejected before they are allocated or read.
+        let mut header = MAGIC.to_vec();
+        for value in [VERSION, 5, 4, 3, 2, 4096, 4096] {
+            header.extend(value.to_le_bytes());
+        }
+        assert!(matches!(
+            Network::read_from(header.as_slice()),
+            Err(NetworkError::InvalidShape)
+        ));
+    }
+}
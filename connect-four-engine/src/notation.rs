@@ -0,0 +1,356 @@
+use crate::bitboard::*;
+use crate::error::EngineError;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+pub const EMPTY_SYMBOL: char = '.';
+pub const FIRST_SYMBOL: char = 'X';
+pub const SECOND_SYMBOL: char = 'O';
+
+// Move strings list the columns played, 1-based, one character per move
+// ("4453" is two stones in the centre column, one either side of it).
+// Boards wider than nine columns continue with lowercase letters, so the
+// tenth column is 'a'.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NotationError {
+    InvalidColumn { index: usize, found: char },
+    IllegalMove { index: usize, error: EngineError },
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotationError::InvalidColumn { index, found } => {
+                write!(f, "move {}: '{}' is not a column", index + 1, found)
+            }
+            NotationError::IllegalMove { index, error } => {
+                write!(f, "move {}: {}", index + 1, error)
+            }
+        }
+    }
+}
+
+impl Error for NotationError {}
+
+/// The symbol of `col`, which must be below `MAX_COLS`.
+pub fn column_to_char(col: usize) -> char {
+    if col < 9 {
+        char::from(b'1' + col as u8)
+    } else {
+        char::from(b'a' + (col - 9) as u8)
+    }
+}
+
+pub fn char_to_column(symbol: char) -> Option<usize> {
+    match symbol {
+        '1'..='9' => Some(symbol as usize - '1' as usize),
+        'a'..='z' => Some(symbol as usize - 'a' as usize + 9),
+        _ => None,
+    }
+}
+
+/// Replays a move string from the empty board under `rules`.
+pub fn parse_moves(rules: Rules, moves: &str) -> Result<Position, NotationError> {
+    let mut position = Position::with_rules(rules);
+    for (index, symbol) in moves.trim().chars().enumerate() {
+        let col = match char_to_column(symbol) {
+            Some(col) if col < rules.size().cols() => col,
+            _ => {
+                return Err(NotationError::InvalidColumn {
+                    index,
+                    found: symbol,
+                });
+            }
+        };
+        position
+            .play(col)
+            .map_err(|error| NotationError::IllegalMove { index, error })?;
+    }
+    Ok(position)
+}
+
+pub fn format_moves(position: &Position) -> String {
+    position
+        .moves()
+        .iter()
+        .map(|&col| column_to_char(col))
+        .collect()
+}
+
+// Diagrams draw the board top row first, one line per row, using
+// EMPTY_SYMBOL, FIRST_SYMBOL and SECOND_SYMBOL. Spaces between cells and
+// blank lines around the board are ignored.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiagramError {
+    InvalidCell { row: usize, col: usize, found: char },
+    RaggedRow { row: usize },
+    UnsupportedSize { cols: usize, rows: usize },
+    FloatingPiece { row: usize, col: usize },
+    PieceCount { first: usize, second: usize },
+    IllegalPosition(EngineError),
+}
+
+impl fmt::Display for DiagramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiagramError::InvalidCell { row, col, found } => {
+                write!(
+                    f,
+                    "row {} column {}: unexpected '{}'",
+                    row + 1,
+                    col + 1,
+                    found
+                )
+            }
+            DiagramError::RaggedRow { row } => {
+                write!(f, "row {} has a different width", row + 1)
+            }
+            DiagramError::UnsupportedSize { cols, rows } => {
+                write!(f, "a {}x{} board is not supported", cols, rows)
+            }
+            DiagramError::FloatingPiece { row, col } => {
+                write!(
+                    f,
+                    "row {} column {}: piece has nothing below it",
+                    row + 1,
+                    col + 1
+                )
+            }
+            DiagramError::PieceCount { first, second } => {
+                write!(
+                    f,
+                    "{} {} and {} {} cannot come from alternating moves",
+                    first, FIRST_SYMBOL, second, SECOND_SYMBOL
+                )
+            }
+            DiagramError::IllegalPosition(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for DiagramError {}
+
+/// Reads a diagram for a board of the drawn size with the given line length.
+/// Rows are numbered from the bottom in errors, matching `Position::cell`.
+pub fn parse_diagram(diagram: &str, connect: usize) -> Result<Position, DiagramError> {
+    let lines: Vec<Vec<char>> = diagram
+        .lines()
+        .map(|line| line.chars().filter(|c| !c.is_whitespace()).collect())
+        .filter(|line: &Vec<char>| !line.is_empty())
+        .collect();
+
+    let rows = lines.len();
+    let cols = lines.first().map_or(0, |line| line.len());
+    let unsupported = DiagramError::UnsupportedSize { cols, rows };
+    let size = BoardSize::new(cols, rows).ok_or(unsupported)?;
+    let rules = Rules::new(size, connect).ok_or(unsupported)?;
+
+    let mut first: Bitboard = 0;
+    let mut second: Bitboard = 0;
+    for (line_index, line) in lines.iter().enumerate() {
+        let row = rows - 1 - line_index;
+        if line.len() != cols {
+            return Err(DiagramError::RaggedRow { row });
+        }
+        for (col, &symbol) in line.iter().enumerate() {
+            let bit: Bitboard = 1 << (col * size.stride() + row);
+            match symbol {
+                EMPTY_SYMBOL => {}
+                FIRST_SYMBOL => first |= bit,
+                SECOND_SYMBOL => second |= bit,
+                found => return Err(DiagramError::InvalidCell { row, col, found }),
+            }
+        }
+    }
+
+    let mask = first | second;
+    let floating = mask & !(mask << 1) & !size.bottom_row_mask();
+    if floating > 0 {
+        let (row, col) = size.indices_from_bitmask(floating).unwrap();
+        return Err(DiagramError::FloatingPiece { row, col });
+    }
+
+    let first_count = first.count_ones() as usize;
+    let second_count = second.count_ones() as usize;
+    if first_count != second_count && first_count != second_count + 1 {
+        return Err(DiagramError::PieceCount {
+            first: first_count,
+            second: second_count,
+        });
+    }
+
+    Position::from_boards(rules, first, second).map_err(DiagramError::IllegalPosition)
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size = self.size();
+        for row in (0..size.rows()).rev() {
+            for col in 0..size.cols() {
+                let symbol = match self.cell(row, col) {
+                    Some(Player::First) => FIRST_SYMBOL,
+                    Some(Player::Second) => SECOND_SYMBOL,
+                    None => EMPTY_SYMBOL,
+                };
+                write!(f, "{}", symbol)?;
+            }
+            if row > 0 {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a connect four diagram of any supported size.
+impl FromStr for Position {
+    type Err = DiagramError;
+
+    fn from_str(diagram: &str) -> Result<Position, DiagramError> {
+        parse_diagram(diagram, Rules::STANDARD.connect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_moves() {
+        let position = parse_moves(Rules::STANDARD, "4453").unwrap();
+        assert_eq!(position.moves(), &[3, 3, 4, 2]);
+        assert_eq!(format_moves(&position), "4453");
+
+        let empty = parse_moves(Rules::STANDARD, "").unwrap();
+        assert_eq!(empty, Position::new());
+    }
+
+    #[test]
+    fn test_parse_moves_errors() {
+        assert_eq!(
+            parse_moves(Rules::STANDARD, "408"),
+            Err(NotationError::InvalidColumn {
+                index: 1,
+                found: '0'
+            })
+        );
+        assert_eq!(
+            parse_moves(Rules::STANDARD, "48"),
+            Err(NotationError::InvalidColumn {
+                index: 1,
+                found: '8'
+            })
+        );
+        assert_eq!(
+            parse_moves(Rules::STANDARD, "1111111"),
+            Err(NotationError::IllegalMove {
+                index: 6,
+                error: EngineError::ColumnFull { col: 0 }
+            })
+        );
+        assert_eq!(
+            parse_moves(Rules::STANDARD, "1111111")
+                .unwrap_err()
+                .to_string(),
+            "move 7: column 1 is full"
+        );
+        assert_eq!(
+            parse_moves(Rules::STANDARD, "12121213"),
+            Err(NotationError::IllegalMove {
+                index: 7,
+                error: EngineError::GameOver
+            })
+        );
+    }
+
+    #[test]
+    fn test_wide_board_moves() {
+        let rules = Rules::new(BoardSize::new(10, 8).unwrap(), 4).unwrap();
+        let position = parse_moves(rules, "a19").unwrap();
+        assert_eq!(position.moves(), &[9, 0, 8]);
+        assert_eq!(format_moves(&position), "a19");
+        for col in 0..MAX_COLS {
+            assert_eq!(char_to_column(column_to_char(col)), Some(col));
+        }
+    }
+
+    #[test]
+    fn test_diagram_round_trip() {
+        let position = parse_moves(Rules::STANDARD, "4453").unwrap();
+        let diagram = "\
+.......
+.......
+.......
+.......
+...O...
+..OXX..";
+        assert_eq!(position.to_string(), diagram);
+
+        let parsed: Position = diagram.parse().unwrap();
+        assert_eq!(parsed.board(Player::First), position.board(Player::First));
+        assert_eq!(parsed.board(Player::Second), position.board(Player::Second));
+        assert_eq!(parsed.side_to_move(), Player::First);
+        assert_eq!(parsed.moves_played(), 4);
+    }
+
+    #[test]
+    fn test_diagram_size_and_spacing() {
+        let position: Position = "
+            . . . . . . . .
+            . . . . . . . .
+            . . . . . . . .
+            . . . . . . . .
+            . . . . . . . .
+            . . . . . . . .
+            X . . . . . . .
+        "
+        .parse()
+        .unwrap();
+        assert_eq!(position.size(), BoardSize::new(8, 7).unwrap());
+        assert_eq!(position.cell(0, 7), None);
+        assert_eq!(position.cell(0, 0), Some(Player::First));
+        assert_eq!(position.cell(7, 0), None);
+        assert_eq!(position.cell(0, 200), None);
+        assert_eq!(position.side_to_move(), Player::Second);
+    }
+
+    #[test]
+    fn test_diagram_errors() {
+        assert_eq!(
+            "...\n.X.\n...".parse::<Position>(),
+            Err(DiagramError::UnsupportedSize { cols: 3, rows: 3 })
+        );
+        assert_eq!(
+            ".....\n..X..\n.....\n.....".parse::<Position>(),
+            Err(DiagramError::FloatingPiece { row: 2, col: 2 })
+        );
+        assert_eq!(
+            ".....\n.....\n.....\n.O...".parse::<Position>(),
+            Err(DiagramError::PieceCount {
+                first: 0,
+                second: 1
+            })
+        );
+        assert_eq!(
+            ".....\n.....\n....\n.....".parse::<Position>(),
+            Err(DiagramError::RaggedRow { row: 1 })
+        );
+        assert_eq!(
+            ".....\n.....\n.....\n.#...".parse::<Position>(),
+            Err(DiagramError::InvalidCell {
+                row: 0,
+                col: 1,
+                found: '#'
+            })
+        );
+        assert_eq!(
+            ".......\n.......\nXO.....\nXO.....\nXO.....\nXO.....".parse::<Position>(),
+            Err(DiagramError::IllegalPosition(
+                EngineError::UnreachablePosition
+            ))
+        );
+    }
+}
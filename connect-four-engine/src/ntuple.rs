@@ -0,0 +1,501 @@
+use crate::bitboard::*;
+use crate::eval::Evaluator;
+use crate::network::{NetworkError, read_f32, read_u32};
+use crate::rng::Rng;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Tuples in a new network unless told otherwise.
+pub const DEFAULT_TUPLES: usize = 48;
+/// Cells per tuple unless told otherwise. Each tuple has a weight for
+/// every combination of states of its cells, so tables grow fourfold with
+/// each extra cell.
+pub const DEFAULT_TUPLE_LENGTH: usize = 7;
+/// Search score of a certain win by the network's estimate. Far below
+/// `bot::WIN_SCORE`, so proven results always rank above estimates.
+pub const NTUPLE_SCALE: f32 = 100.0;
+
+const MAGIC: &[u8; 4] = b"C4NT";
+const VERSION: u32 = 1;
+// Empty, empty and playable now, and a stone of either player.
+const STATES: usize = 4;
+const MAX_TUPLE_LENGTH: usize = 12;
+// Guards against allocating gigabytes for a corrupt header: one tuple of
+// the greatest length, or 64 MB of weights.
+const MAX_WEIGHTS: usize = STATES.pow(MAX_TUPLE_LENGTH as u32);
+
+/// An n-tuple network: a lookup table per small group of cells, indexed by
+/// what those cells hold. The sum of the looked-up weights estimates the
+/// result, so evaluating costs a few table lookups. Every tuple is also
+/// read mirrored, since the rules are symmetric.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NTupleNetwork {
+    rules: Rules,
+    tuples: Vec<Tuple>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Tuple {
+    /// Bit positions of the cells, and of their mirror images.
+    cells: Vec<usize>,
+    mirrored: Vec<usize>,
+    weights: Vec<f32>,
+}
+
+/// Settings of temporal-difference training.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TdParams {
+    pub learning_rate: f32,
+    /// How much of each error is passed back to earlier positions, from 0
+    /// for only the last position to 1 for the whole game.
+    pub lambda: f32,
+    /// Chance of a random move instead of the best one. Nothing is learnt
+    /// across random moves.
+    pub exploration: f64,
+}
+
+impl Default for TdParams {
+    fn default() -> TdParams {
+        TdParams {
+            learning_rate: 0.004,
+            lambda: 0.5,
+            exploration: 0.1,
+        }
+    }
+}
+
+/// Bitboards a position's tuple indices are read from.
+struct Cells {
+    first: Bitboard,
+    second: Bitboard,
+    playable: Bitboard,
+}
+
+impl Cells {
+    fn new(position: &Position) -> Cells {
+        Cells {
+            first: position.board(Player::First),
+            second: position.board(Player::Second),
+            playable: position.size().playable(position.mask()),
+        }
+    }
+
+    fn index(&self, cells: &[usize]) -> usize {
+        cells.iter().fold(0, |index, &bit| {
+            let state = if self.first >> bit & 1 != 0 {
+                2
+            } else if self.second >> bit & 1 != 0 {
+                3
+            } else if self.playable >> bit & 1 != 0 {
+                1
+            } else {
+                0
+            };
+            index * STATES + state
+        })
+    }
+}
+
+impl NTupleNetwork {
+    /// A network of `tuples` random snakes of `length` neighbouring cells,
+    /// with every weight zero. `length` is clamped to what the board and
+    /// memory allow.
+    pub fn new(rules: Rules, tuples: usize, length: usize, rng: &mut Rng) -> NTupleNetwork {
+        let size = rules.size();
+        let length = length.clamp(1, size.cells().min(MAX_TUPLE_LENGTH));
+        NTupleNetwork {
+            rules,
+            tuples: (0..tuples)
+                .map(|_| Tuple::new(size, random_snake(size, length, rng)))
+                .collect(),
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        self.rules
+    }
+
+    /// Expected result for the first player, from -1 for a loss to 1 for a
+    /// win.
+    pub fn value(&self, position: &Position) -> f32 {
+        let cells = Cells::new(position);
+        let sum: f32 = self
+            .tuples
+            .iter()
+            .map(|tuple| {
+                tuple.weights[cells.index(&tuple.cells)]
+                    + tuple.weights[cells.index(&tuple.mirrored)]
+            })
+            .sum();
+        sum.tanh()
+    }
+
+    /// The table entries `value` reads for `position`, as tuple and index
+    /// pairs.
+    fn features(&self, position: &Position) -> Vec<(usize, usize)> {
+        let cells = Cells::new(position);
+        self.tuples
+            .iter()
+            .enumerate()
+            .flat_map(|(i, tuple)| {
+                [
+                    (i, cells.index(&tuple.cells)),
+                    (i, cells.index(&tuple.mirrored)),
+                ]
+            })
+            .collect()
+    }
+
+    /// The move whose resulting position is best for the side to move by
+    /// the network's estimate, taking any immediate win.
+    pub fn best_move(&self, position: &Position) -> usize {
+        let side = position.side_to_move();
+        let size = position.size();
+        let mut child = position.clone();
+        let mut best = None;
+        for col in size.centre_out() {
+            if !child.can_play(col) {
+                continue;
+            }
+            child.play_unchecked(col);
+            let value = match child.outcome() {
+                Outcome::Won(_) => return col,
+                Outcome::Draw => 0.0,
+                Outcome::InProgress => self.value(&child),
+            };
+            child.undo();
+            let score = if side == Player::First { value } else { -value };
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((col, score));
+            }
+        }
+        best.unwrap().0
+    }
+
+    /// Plays one game against itself and learns from it by TD(λ): after
+    /// each move, the estimate of every earlier position moves towards the
+    /// estimate of the new one, or towards the result once the game ends.
+    pub fn train_game(&mut self, params: &TdParams, rng: &mut Rng) -> Outcome {
+        // Older positions receive a share of each error shrinking by
+        // `lambda` per move, so past this many it no longer matters.
+        let horizon = if params.lambda > 0.0 {
+            (0.01f32.ln() / params.lambda.ln()).ceil().clamp(1.0, 64.0) as usize
+        } else {
+            1
+        };
+        let mut position = Position::with_rules(self.rules);
+        let mut history: VecDeque<(Vec<(usize, usize)>, f32)> = VecDeque::new();
+        let mut value = self.value(&position);
+        loop {
+            let explore = rng.next_f64() < params.exploration;
+            let col = if explore {
+                let size = position.size();
+                rng.choose(&size.columns(size.playable(position.mask())))
+                    .unwrap()
+            } else {
+                self.best_move(&position)
+            };
+            let features = self.features(&position);
+            position.play_unchecked(col);
+
+            let outcome = position.outcome();
+            let target = match outcome {
+                Outcome::Won(Player::First) => 1.0,
+                Outcome::Won(Player::Second) => -1.0,
+                Outcome::Draw => 0.0,
+                Outcome::InProgress => self.value(&position),
+            };
+            if explore {
+                history.clear();
+            } else {
+                // The derivative of tanh scales each position's gradient.
+                history.push_front((features, 1.0 - value * value));
+                history.truncate(horizon);
+                let mut step = params.learning_rate * (target - value);
+                for (features, gradient) in &history {
+                    for &(tuple, index) in features {
+                        self.tuples[tuple].weights[index] += step * gradient;
+                    }
+                    step *= params.lambda;
+                }
+            }
+            if outcome != Outcome::InProgress {
+                return outcome;
+            }
+            value = self.value(&position);
+        }
+    }
+
+    /// Whether the file at `path` starts with the header `write_to` puts
+    /// first. Files too short to hold it are not networks.
+    pub fn is_network_file(path: impl AsRef<Path>) -> io::Result<bool> {
+        let mut magic = [0; 4];
+        match File::open(path)?.read_exact(&mut magic) {
+            Ok(()) => Ok(&magic == MAGIC),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<NTupleNetwork, NetworkError> {
+        NTupleNetwork::read_from(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), NetworkError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads the format written by `write_to`: a header with the rules and
+    /// the cells of every tuple, then every weight as a little-endian
+    /// `f32`.
+    pub fn read_from(mut reader: impl Read) -> Result<NTupleNetwork, NetworkError> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(NetworkError::NotANetwork);
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(NetworkError::UnsupportedVersion { version });
+        }
+        let cols = read_u32(&mut reader)? as usize;
+        let rows = read_u32(&mut reader)? as usize;
+        let connect = read_u32(&mut reader)? as usize;
+        let rules = BoardSize::new(cols, rows)
+            .and_then(|size| Rules::new(size, connect))
+            .ok_or(NetworkError::InvalidShape)?;
+        let size = rules.size();
+
+        let count = read_u32(&mut reader)? as usize;
+        let mut tuples = Vec::new();
+        let mut weights = 0;
+        for _ in 0..count {
+            let length = read_u32(&mut reader)? as usize;
+            if !(1..=MAX_TUPLE_LENGTH).contains(&length) {
+                return Err(NetworkError::InvalidShape);
+            }
+            weights += STATES.pow(length as u32);
+            if weights > MAX_WEIGHTS {
+                return Err(NetworkError::InvalidShape);
+            }
+            let mut cells = Vec::with_capacity(length);
+            for _ in 0..length {
+                let bit = read_u32(&mut reader)? as usize;
+                if bit / size.stride() >= cols || bit % size.stride() >= rows {
+                    return Err(NetworkError::InvalidShape);
+                }
+                cells.push(bit);
+            }
+            tuples.push(Tuple::new(size, cells));
+        }
+        for tuple in &mut tuples {
+            for weight in &mut tuple.weights {
+                *weight = read_f32(&mut reader)?;
+                if !weight.is_finite() {
+                    return Err(NetworkError::InvalidWeight);
+                }
+            }
+        }
+        if reader.read(&mut [0])? > 0 {
+            return Err(NetworkError::InvalidShape);
+        }
+        Ok(NTupleNetwork { rules, tuples })
+    }
+
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let size = self.rules.size();
+        writer.write_all(MAGIC)?;
+        for value in [
+            VERSION,
+            size.cols() as u32,
+            size.rows() as u32,
+            self.rules.connect() as u32,
+            self.tuples.len() as u32,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for tuple in &self.tuples {
+            writer.write_all(&(tuple.cells.len() as u32).to_le_bytes())?;
+            for &bit in &tuple.cells {
+                writer.write_all(&(bit as u32).to_le_bytes())?;
+            }
+        }
+        for tuple in &self.tuples {
+            for weight in &tuple.weights {
+                writer.write_all(&weight.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Scores the network's estimate for the side to move, scaled by
+/// `NTUPLE_SCALE`.
+impl Evaluator for NTupleNetwork {
+    fn evaluate(&self, position: &Position) -> f32 {
+        let value = self.value(position) * NTUPLE_SCALE;
+        match position.side_to_move() {
+            Player::First => value,
+            Player::Second => -value,
+        }
+    }
+
+    fn rules(&self) -> Option<Rules> {
+        Some(self.rules)
+    }
+}
+
+impl Tuple {
+    fn new(size: BoardSize, cells: Vec<usize>) -> Tuple {
+        let stride = size.stride();
+        let mirrored = cells
+            .iter()
+            .map(|&bit| (size.cols() - 1 - bit / stride) * stride + bit % stride)
+            .collect();
+        Tuple {
+            weights: vec![0.0; STATES.pow(cells.len() as u32)],
+            cells,
+            mirrored,
+        }
+    }
+}
+
+/// Bit positions of `length` distinct cells, each a neighbour of the one
+/// before in any of the eight directions.
+fn random_snake(size: BoardSize, length: usize, rng: &mut Rng) -> Vec<usize> {
+    let (cols, rows) = (size.cols() as isize, size.rows() as isize);
+    loop {
+        let mut cells = vec![(
+            rng.below(size.cols()) as isize,
+            rng.below(size.rows()) as isize,
+        )];
+        while cells.len() < length {
+            let (col, row) = *cells.last().unwrap();
+            let mut neighbours = Vec::new();
+            for dc in -1..=1 {
+                for dr in -1..=1 {
+                    let cell = (col + dc, row + dr);
+                    if (0..cols).contains(&cell.0)
+                        && (0..rows).contains(&cell.1)
+                        && !cells.contains(&cell)
+                    {
+                        neighbours.push(cell);
+                    }
+                }
+            }
+            match rng.choose(&neighbours) {
+                Some(cell) => cells.push(cell),
+                // Walked into a dead end, so start again.
+                None => break,
+            }
+        }
+        if cells.len() == length {
+            return cells
+                .into_iter()
+                .map(|(col, row)| col as usize * size.stride() + row as usize)
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::{SearchHandle, SearchLimits, search_with_handle};
+    use crate::error::EngineError;
+    use crate::notation::parse_moves;
+    use crate::transposition::TranspositionTable;
+
+    #[test]
+    fn test_value_is_mirror_symmetric() {
+        let mut network = NTupleNetwork::new(Rules::STANDARD, 8, 6, &mut Rng::new(1));
+        for tuple in &mut network.tuples {
+            for (i, weight) in tuple.weights.iter_mut().enumerate() {
+                *weight = (i % 13) as f32 * 0.01 - 0.06;
+            }
+        }
+        let position = parse_moves(Rules::STANDARD, "4452213").unwrap();
+        let mirrored = parse_moves(Rules::STANDARD, "4436675").unwrap();
+        assert!((network.value(&position) - network.value(&mirrored)).abs() < 1e-6);
+        // First's estimate counts against Second when Second is to move.
+        let value = network.value(&position);
+        assert_eq!(network.evaluate(&position), -value * NTUPLE_SCALE);
+    }
+
+    #[test]
+    fn test_td_learns_from_self_play() {
+        let rules = Rules::new(BoardSize::new(4, 4).unwrap(), 3).unwrap();
+        let mut network = NTupleNetwork::new(rules, 8, 4, &mut Rng::new(2));
+        let mut rng = Rng::new(3);
+        for _ in 0..300 {
+            network.train_game(&TdParams::default(), &mut rng);
+        }
+        // The first player wins 3-in-a-row on a 4x4 board by starting in
+        // the middle, and the network should have noticed.
+        let position = Position::with_rules(rules);
+        assert!(network.value(&position) > 0.0);
+        // A ready-made win is taken rather than evaluated.
+        let position = parse_moves(rules, "1213").unwrap();
+        assert_eq!(network.best_move(&position), 0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut network = NTupleNetwork::new(Rules::STANDARD, 4, 3, &mut Rng::new(4));
+        network.tuples[2].weights[5] = 0.5;
+        let mut bytes = Vec::new();
+        network.write_to(&mut bytes).unwrap();
+        assert_eq!(NTupleNetwork::read_from(bytes.as_slice()).unwrap(), network);
+        assert!(matches!(
+            NTupleNetwork::read_from(&bytes[..bytes.len() - 1]),
+            Err(NetworkError::Io(_))
+        ));
+        assert!(matches!(
+            NTupleNetwork::read_from(&b"C4NN"[..]),
+            Err(NetworkError::NotANetwork)
+        ));
+
+        network.tuples[2].weights[5] = f32::NAN;
+        let mut bytes = Vec::new();
+        network.write_to(&mut bytes).unwrap();
+        assert!(matches!(
+            NTupleNetwork::read_from(bytes.as_slice()),
+            Err(NetworkError::InvalidWeight)
+        ));
+
+        // Two tuples of twelve cells would need 128 MB of weights.
+        let mut bytes = MAGIC.to_vec();
+        let cells = [0, 1, 2, 3, 4, 5, 7, 8, 9, 10, 11, 12];
+        for value in [VERSION, 7, 6, 4, 2, 12]
+            .into_iter()
+            .chain(cells)
+            .chain([12])
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        assert!(matches!(
+            NTupleNetwork::read_from(bytes.as_slice()),
+            Err(NetworkError::InvalidShape)
+        ));
+    }
+
+    #[test]
+    fn test_search_rejects_other_rules() {
+        let network = NTupleNetwork::new(Rules::STANDARD, 4, 3, &mut Rng::new(5));
+        let rules = Rules::new(BoardSize::new(6, 5).unwrap(), 4).unwrap();
+        let result = search_with_handle(
+            &Position::with_rules(rules),
+            SearchLimits::depth(2),
+            &network,
+            &TranspositionTable::default(),
+            &SearchHandle::new(),
+            |_| {},
+        );
+        assert_eq!(result.err(), Some(EngineError::RulesMismatch));
+    }
+}
@@ -0,0 +1,341 @@
+use crate::bitboard::*;
+use crate::bot::SearchHandle;
+use crate::error::EngineError;
+use crate::network::Network;
+use crate::rng::Rng;
+use std::time::{Duration, Instant};
+
+/// Budget and tuning of a network-guided search.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PuctParams {
+    /// Network evaluations per move.
+    pub simulations: usize,
+    /// Weight of the network's prior against the values found so far.
+    /// Higher values follow the policy further before trusting results.
+    pub exploration: f32,
+}
+
+impl PuctParams {
+    pub fn simulations(simulations: usize) -> PuctParams {
+        PuctParams {
+            simulations,
+            ..PuctParams::default()
+        }
+    }
+}
+
+impl Default for PuctParams {
+    fn default() -> PuctParams {
+        PuctParams {
+            simulations: 400,
+            exploration: 1.5,
+        }
+    }
+}
+
+/// Dirichlet noise mixed into the root's priors, as AlphaZero does in
+/// self-play, so games try moves the network does not favour yet.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RootNoise {
+    /// Concentration of the Dirichlet distribution. Smaller values put
+    /// the noise on fewer moves.
+    pub alpha: f32,
+    /// Share of each prior replaced by noise.
+    pub fraction: f32,
+}
+
+impl Default for RootNoise {
+    fn default() -> RootNoise {
+        RootNoise {
+            alpha: 1.0,
+            fraction: 0.25,
+        }
+    }
+}
+
+/// Outcome of a search from the side to move's point of view.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PuctResult {
+    pub best_move: usize,
+    /// Mean value of the simulations through `best_move`, from -1 for a
+    /// loss to 1 for a win.
+    pub value: f32,
+    pub simulations: usize,
+    pub elapsed: Duration,
+    /// Simulations through each legal column, in column order. Self-play
+    /// trains the policy towards these counts.
+    pub visits: Vec<(usize, u64)>,
+}
+
+/// Searches `position` AlphaZero-style: the tree grows where the network's
+/// policy and the values backed up so far point, and leaves are valued by
+/// the network instead of by playouts.
+pub fn puct(
+    position: &Position,
+    network: &Network,
+    params: &PuctParams,
+) -> Result<PuctResult, EngineError> {
+    puct_with_handle(position, network, params, &SearchHandle::new())
+}
+
+/// Like `puct`, but stops early once `handle` is stopped. The root is
+/// always expanded, so there is always a move.
+pub fn puct_with_handle(
+    position: &Position,
+    network: &Network,
+    params: &PuctParams,
+    handle: &SearchHandle,
+) -> Result<PuctResult, EngineError> {
+    run(position, network, params, handle, None)
+}
+
+/// Like `puct`, but with `noise` mixed into the root's priors.
+pub fn puct_with_noise(
+    position: &Position,
+    network: &Network,
+    params: &PuctParams,
+    noise: &RootNoise,
+    rng: &mut Rng,
+) -> Result<PuctResult, EngineError> {
+    run(
+        position,
+        network,
+        params,
+        &SearchHandle::new(),
+        Some((noise, rng)),
+    )
+}
+
+fn run(
+    position: &Position,
+    network: &Network,
+    params: &PuctParams,
+    handle: &SearchHandle,
+    mut noise: Option<(&RootNoise, &mut Rng)>,
+) -> Result<PuctResult, EngineError> {
+    if position.is_game_over() {
+        return Err(EngineError::GameOver);
+    }
+    if position.rules() != network.rules() {
+        return Err(EngineError::RulesMismatch);
+    }
+    let started = Instant::now();
+    let mut tree = Tree {
+        nodes: vec![Node::new(usize::MAX, 1.0)],
+    };
+    let mut path = Vec::new();
+    let mut simulations = 0;
+    while simulations < params.simulations.max(1) {
+        tree.simulate(position, network, params.exploration, &mut path);
+        simulations += 1;
+        // The first simulation expands the root without visiting any
+        // child, so the noise is in place before any is chosen.
+        if let Some((noise, rng)) = noise.take() {
+            tree.add_root_noise(noise, rng);
+        }
+        if handle.is_stopped() {
+            break;
+        }
+    }
+
+    let root = &tree.nodes[0];
+    let best = root
+        .children
+        .iter()
+        .map(|&child| &tree.nodes[child as usize])
+        .max_by(|a, b| a.visits.cmp(&b.visits).then(a.prior.total_cmp(&b.prior)))
+        .unwrap();
+    let mut visits: Vec<(usize, u64)> = root
+        .children
+        .iter()
+        .map(|&child| {
+            let node = &tree.nodes[child as usize];
+            (node.col, u64::from(node.visits))
+        })
+        .collect();
+    visits.sort_unstable();
+
+    Ok(PuctResult {
+        best_move: best.col,
+        value: best.mean_value(),
+        simulations,
+        elapsed: started.elapsed(),
+        visits,
+    })
+}
+
+struct Node {
+    /// Column played to reach this node from its parent.
+    col: usize,
+    /// The network's probability for `col` at the parent.
+    prior: f32,
+    visits: u32,
+    /// Sum of the values backed up through this node, for the player who
+    /// played `col`.
+    value: f32,
+    children: Vec<u32>,
+}
+
+impl Node {
+    fn new(col: usize, prior: f32) -> Node {
+        Node {
+            col,
+            prior,
+            visits: 0,
+            value: 0.0,
+            children: Vec::new(),
+        }
+    }
+
+    fn mean_value(&self) -> f32 {
+        if self.visits == 0 {
+            0.0
+        } else {
+            self.value / self.visits as f32
+        }
+    }
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+impl Tree {
+    /// Walks down to a leaf, expands it with the network's policy and
+    /// backs its value up the path.
+    fn simulate(
+        &mut self,
+        root: &Position,
+        network: &Network,
+        exploration: f32,
+        path: &mut Vec<u32>,
+    ) {
+        let mut position = root.clone();
+        let mut index = 0;
+        path.clear();
+        path.push(0);
+        while !self.nodes[index].children.is_empty() {
+            index = self.select(index, exploration);
+            position.play_unchecked(self.nodes[index].col);
+            path.push(index as u32);
+        }
+
+        // Values are from the side to move at the leaf.
+        let value = match position.outcome() {
+            // Only the player who just moved can have won.
+            Outcome::Won(_) => -1.0,
+            Outcome::Draw => 0.0,
+            Outcome::InProgress => {
+                let prediction = network.evaluate(&position);
+                for (col, &prior) in prediction.policy.iter().enumerate() {
+                    if position.can_play(col) {
+                        let child = self.nodes.len() as u32;
+                        self.nodes.push(Node::new(col, prior));
+                        self.nodes[index].children.push(child);
+                    }
+                }
+                prediction.value
+            }
+        };
+
+        // Each node holds values for the player who moved into it, the
+        // opponent of the side to move there.
+        let mut value = -value;
+        for &index in path.iter().rev() {
+            let node = &mut self.nodes[index as usize];
+            node.visits += 1;
+            node.value += value;
+            value = -value;
+        }
+    }
+
+    fn add_root_noise(&mut self, noise: &RootNoise, rng: &mut Rng) {
+        let children = self.nodes[0].children.clone();
+        let samples: Vec<f64> = children
+            .iter()
+            .map(|_| rng.gamma(noise.alpha as f64))
+            .collect();
+        let total: f64 = samples.iter().sum();
+        if total <= 0.0 {
+            return;
+        }
+        for (&child, sample) in children.iter().zip(samples) {
+            let node = &mut self.nodes[child as usize];
+            node.prior =
+                (1.0 - noise.fraction) * node.prior + noise.fraction * (sample / total) as f32;
+        }
+    }
+
+    /// The child of `parent` maximising its mean value plus a bonus for
+    /// moves the policy likes but the search has rarely tried. Unvisited
+    /// children count as even.
+    fn select(&self, parent: usize, exploration: f32) -> usize {
+        let scale = exploration * (self.nodes[parent].visits as f32).sqrt();
+        let score = |&child: &u32| {
+            let node = &self.nodes[child as usize];
+            node.mean_value() + scale * node.prior / (1.0 + node.visits as f32)
+        };
+        self.nodes[parent]
+            .children
+            .iter()
+            .max_by(|a, b| score(a).total_cmp(&score(b)))
+            .map(|&child| child as usize)
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+    use crate::rng::Rng;
+
+    #[test]
+    fn test_puct_finds_wins_with_untrained_network() {
+        let network = Network::new(Rules::STANDARD, &[16], &mut Rng::new(1));
+        let params = PuctParams::simulations(300);
+        // First wins in column 4 rather than blocking column 7; terminal
+        // values steer the search even when the network knows nothing.
+        let position = parse_moves(Rules::STANDARD, "172737").unwrap();
+        let result = puct(&position, &network, &params).unwrap();
+        assert_eq!(result.best_move, 3);
+        assert!(result.value > 0.9);
+        assert_eq!(result.simulations, 300);
+        assert_eq!(result.visits.iter().map(|v| v.1).sum::<u64>(), 299);
+
+        let other = Rules::new(BoardSize::new(6, 6).unwrap(), 4).unwrap();
+        assert_eq!(
+            puct(&Position::with_rules(other), &network, &params),
+            Err(EngineError::RulesMismatch)
+        );
+    }
+
+    #[test]
+    fn test_root_noise() {
+        let network = Network::new(Rules::STANDARD, &[16], &mut Rng::new(1));
+        let params = PuctParams::simulations(50);
+        let noise = RootNoise {
+            alpha: 0.3,
+            fraction: 1.0,
+        };
+        // With every prior replaced by noise, different seeds spread the
+        // search differently.
+        let visits: Vec<Vec<(usize, u64)>> = (0..4)
+            .map(|seed| {
+                puct_with_noise(
+                    &Position::new(),
+                    &network,
+                    &params,
+                    &noise,
+                    &mut Rng::new(seed),
+                )
+                .unwrap()
+                .visits
+            })
+            .collect();
+        assert!(visits.iter().any(|v| *v != visits[0]));
+        for v in &visits {
+            assert_eq!(v.iter().map(|v| v.1).sum::<u64>(), 49);
+        }
+    }
+}
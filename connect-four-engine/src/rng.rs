@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Small pseudo-random generator (SplitMix64). Not suitable for secrets,
+/// but fast and reproducible from a seed, which self-play and tests need.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// Seeded from the clock, so each run plays differently. Generators
+    /// created at the same instant still get different seeds.
+    pub fn from_time() -> Rng {
+        static CREATED: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        let count = CREATED.fetch_add(1, Ordering::Relaxed);
+        Rng::new(nanos ^ count.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `0..n`. `n` must not be zero.
+    pub fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+
+    /// Uniform in `0.0..1.0`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Gamma distributed with scale one, by Marsaglia and Tsang's method.
+    /// `shape` must be positive.
+    pub fn gamma(&mut self, shape: f64) -> f64 {
+        if shape < 1.0 {
+            // Boost the shape above one and scale the sample back down.
+            let u = 1.0 - self.next_f64();
+            return self.gamma(shape + 1.0) * u.powf(1.0 / shape);
+        }
+        let d = shape - 1.0 / 3.0;
+        let c = 1.0 / (9.0 * d).sqrt();
+        loop {
+            let x = self.normal();
+            let v = (1.0 + c * x).powi(3);
+            if v <= 0.0 {
+                continue;
+            }
+            let u = 1.0 - self.next_f64();
+            if u.ln() < x * x / 2.0 + d - d * v + d * v.ln() {
+                return d * v;
+            }
+        }
+    }
+
+    /// Standard normal, by the Box-Muller transform.
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+    }
+
+    pub fn choose<T: Copy>(&mut self, items: &[T]) -> Option<T> {
+        if items.is_empty() {
+            return None;
+        }
+        Some(items[self.below(items.len())])
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Rng {
+        Rng::from_time()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+
+        let mut counts = [0; 7];
+        for _ in 0..7000 {
+            counts[a.below(7)] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 800 && count < 1200));
+
+        let x = a.next_f64();
+        assert!((0.0..1.0).contains(&x));
+        assert_eq!(a.choose::<usize>(&[]), None);
+        assert_eq!(a.choose(&[3]), Some(3));
+
+        for shape in [0.3, 1.0, 4.0] {
+            let mean = (0..4000).map(|_| a.gamma(shape)).sum::<f64>() / 4000.0;
+            assert!((mean - shape).abs() < 0.1 * shape.max(1.0), "{}", mean);
+        }
+    }
+}
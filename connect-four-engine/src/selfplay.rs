@@ -0,0 +1,298 @@
+use crate::bitboard::*;
+use crate::network::{Loss, Network, NetworkError, Sample, encode};
+use crate::puct::{PuctParams, RootNoise, puct_with_noise};
+use crate::rng::Rng;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Settings of the self-play training loop.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TrainingParams {
+    /// Self-play games per iteration, played in parallel.
+    pub games: usize,
+    pub search: PuctParams,
+    /// Noise added at the root of every search, so games explore beyond
+    /// the network's current favourites.
+    pub noise: RootNoise,
+    /// Moves at the start of each game chosen in proportion to the search
+    /// visits rather than by the most visits, so games differ.
+    pub temperature_moves: usize,
+    /// Samples kept from recent games to train on.
+    pub buffer_size: usize,
+    /// Gradient steps per iteration.
+    pub batches: usize,
+    pub batch_size: usize,
+    pub learning_rate: f32,
+    pub weight_decay: f32,
+}
+
+impl Default for TrainingParams {
+    fn default() -> TrainingParams {
+        TrainingParams {
+            games: 32,
+            search: PuctParams::simulations(100),
+            noise: RootNoise::default(),
+            temperature_moves: 8,
+            buffer_size: 20_000,
+            batches: 200,
+            batch_size: 64,
+            learning_rate: 0.02,
+            weight_decay: 1e-4,
+        }
+    }
+}
+
+/// What happened in one iteration of `Trainer::run_iteration`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct IterationStats {
+    /// Counted from one.
+    pub iteration: usize,
+    pub first_wins: usize,
+    pub second_wins: usize,
+    pub draws: usize,
+    /// Samples in the buffer after adding this iteration's games.
+    pub samples: usize,
+    /// Mean loss over the iteration's training batches.
+    pub loss: Loss,
+    pub elapsed: Duration,
+}
+
+/// Improves a network by playing it against itself and training it on
+/// the results: the policy towards the search's visit counts, the value
+/// towards each game's outcome.
+pub struct Trainer {
+    network: Network,
+    params: TrainingParams,
+    buffer: VecDeque<Sample>,
+    rng: Rng,
+    iteration: usize,
+}
+
+impl Trainer {
+    pub fn new(network: Network, params: TrainingParams, rng: Rng) -> Trainer {
+        Trainer {
+            network,
+            params,
+            buffer: VecDeque::new(),
+            rng,
+            iteration: 0,
+        }
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    pub fn into_network(self) -> Network {
+        self.network
+    }
+
+    /// Plays a round of self-play games, then trains on the buffer.
+    pub fn run_iteration(&mut self) -> IterationStats {
+        let started = Instant::now();
+        self.iteration += 1;
+
+        let seeds: Vec<u64> = (0..self.params.games)
+            .map(|_| self.rng.next_u64())
+            .collect();
+        let games: Vec<(Vec<Sample>, Outcome)> = seeds
+            .into_par_iter()
+            .map(|seed| self_play(&self.network, &self.params, &mut Rng::new(seed)))
+            .collect();
+
+        let mut stats = IterationStats {
+            iteration: self.iteration,
+            first_wins: 0,
+            second_wins: 0,
+            draws: 0,
+            samples: 0,
+            loss: Loss::default(),
+            elapsed: Duration::ZERO,
+        };
+        let size = self.network.rules().size();
+        for (samples, outcome) in games {
+            match outcome {
+                Outcome::Won(Player::First) => stats.first_wins += 1,
+                Outcome::Won(Player::Second) => stats.second_wins += 1,
+                _ => stats.draws += 1,
+            }
+            for sample in samples {
+                // The rules are symmetric, so every position teaches its
+                // mirror image too.
+                self.buffer.push_back(mirror(&sample, size));
+                self.buffer.push_back(sample);
+            }
+        }
+        while self.buffer.len() > self.params.buffer_size {
+            self.buffer.pop_front();
+        }
+        stats.samples = self.buffer.len();
+
+        if !self.buffer.is_empty() {
+            let mut batch = Vec::with_capacity(self.params.batch_size);
+            for _ in 0..self.params.batches {
+                batch.clear();
+                for _ in 0..self.params.batch_size {
+                    let index = self.rng.below(self.buffer.len());
+                    batch.push(self.buffer[index].clone());
+                }
+                let loss =
+                    self.network
+                        .train(&batch, self.params.learning_rate, self.params.weight_decay);
+                stats.loss.policy += loss.policy;
+                stats.loss.value += loss.value;
+            }
+            let batches = self.params.batches.max(1) as f32;
+            stats.loss.policy /= batches;
+            stats.loss.value /= batches;
+        }
+        stats.elapsed = started.elapsed();
+        stats
+    }
+
+    /// Runs `iterations` iterations, saving the network to `dir` after
+    /// each as `iteration-NNNN.net` and as `latest.net`.
+    pub fn train(
+        &mut self,
+        iterations: usize,
+        dir: impl AsRef<Path>,
+        mut on_iteration: impl FnMut(&IterationStats),
+    ) -> Result<(), NetworkError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for _ in 0..iterations {
+            let stats = self.run_iteration();
+            self.network
+                .save(dir.join(format!("iteration-{:04}.net", stats.iteration)))?;
+            self.network.save(dir.join("latest.net"))?;
+            on_iteration(&stats);
+        }
+        Ok(())
+    }
+}
+
+/// Plays one game of `network` against itself and returns a sample per
+/// position, valued by the game's outcome for the side to move there.
+pub fn self_play(
+    network: &Network,
+    params: &TrainingParams,
+    rng: &mut Rng,
+) -> (Vec<Sample>, Outcome) {
+    let cols = network.rules().size().cols();
+    let mut position = Position::with_rules(network.rules());
+    let mut moves = Vec::new();
+    while !position.is_game_over() {
+        let result =
+            puct_with_noise(&position, network, &params.search, &params.noise, rng).unwrap();
+        let total: u64 = result.visits.iter().map(|v| v.1).sum();
+        let mut policy = vec![0.0; cols];
+        for &(col, visits) in &result.visits {
+            policy[col] = visits as f32 / total.max(1) as f32;
+        }
+
+        let col = if position.moves_played() < params.temperature_moves && total > 0 {
+            let mut pick = rng.below(total as usize) as u64;
+            result
+                .visits
+                .iter()
+                .find(|&&(_, visits)| {
+                    if pick < visits {
+                        return true;
+                    }
+                    pick -= visits;
+                    false
+                })
+                .map_or(result.best_move, |&(col, _)| col)
+        } else {
+            result.best_move
+        };
+        moves.push((encode(&position), policy, position.side_to_move()));
+        position.play_unchecked(col);
+    }
+
+    let outcome = position.outcome();
+    let samples = moves
+        .into_iter()
+        .map(|(input, policy, side)| Sample {
+            input,
+            policy,
+            value: match outcome {
+                Outcome::Won(winner) if winner == side => 1.0,
+                Outcome::Won(_) => -1.0,
+                _ => 0.0,
+            },
+        })
+        .collect();
+    (samples, outcome)
+}
+
+/// `sample` with the board and policy flipped left to right.
+fn mirror(sample: &Sample, size: BoardSize) -> Sample {
+    let (cols, rows) = (size.cols(), size.rows());
+    let mut input = vec![0.0; sample.input.len()];
+    for (plane, cells) in sample.input.chunks_exact(cols * rows).enumerate() {
+        for (col, column) in cells.chunks_exact(rows).enumerate() {
+            let start = plane * cols * rows + (cols - 1 - col) * rows;
+            input[start..start + rows].copy_from_slice(column);
+        }
+    }
+    Sample {
+        input,
+        policy: sample.policy.iter().rev().copied().collect(),
+        value: sample.value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+
+    #[test]
+    fn test_self_play_and_training() {
+        let rules = Rules::new(BoardSize::new(5, 4).unwrap(), 3).unwrap();
+        let network = Network::new(rules, &[16], &mut Rng::new(1));
+        let params = TrainingParams {
+            games: 4,
+            search: PuctParams::simulations(20),
+            batches: 5,
+            batch_size: 8,
+            ..TrainingParams::default()
+        };
+
+        let (samples, outcome) = self_play(&network, &params, &mut Rng::new(2));
+        assert_ne!(outcome, Outcome::InProgress);
+        for sample in &samples {
+            assert!((sample.policy.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        }
+        // The last mover's sample is valued by the outcome.
+        let last = samples.last().unwrap();
+        assert_eq!(last.value, if outcome == Outcome::Draw { 0.0 } else { 1.0 });
+
+        let mut trainer = Trainer::new(network, params, Rng::new(3));
+        let stats = trainer.run_iteration();
+        assert_eq!(stats.iteration, 1);
+        assert_eq!(stats.first_wins + stats.second_wins + stats.draws, 4);
+        assert!(stats.samples > 0 && stats.samples.is_multiple_of(2));
+        assert!(stats.loss.policy > 0.0);
+    }
+
+    #[test]
+    fn test_mirror() {
+        let position = parse_moves(Rules::STANDARD, "112").unwrap();
+        let mirrored = parse_moves(Rules::STANDARD, "776").unwrap();
+        let mut policy = vec![0.0; 7];
+        policy[1] = 1.0;
+        let sample = Sample {
+            input: encode(&position),
+            policy,
+            value: 1.0,
+        };
+        let flipped = mirror(&sample, BoardSize::STANDARD);
+        assert_eq!(flipped.input, encode(&mirrored));
+        assert_eq!(flipped.policy[5], 1.0);
+    }
+}
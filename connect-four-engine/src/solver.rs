@@ -0,0 +1,520 @@
+use crate::bitboard::*;
+use crate::bot::SearchHandle;
+use crate::error::EngineError;
+use crate::transposition::{Bound, Entry, TranspositionTable};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Scores follow Pascal Pons' solver: a win scores one more than the number
+// of stones the winner still had in hand when the game ended, a loss the
+// negation of that, and a draw zero. Faster wins therefore score higher.
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Value {
+    Win,
+    Draw,
+    Loss,
+}
+
+/// The game-theoretic result of a position for the side to move.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Solution {
+    score: i32,
+    plies: usize,
+}
+
+impl Solution {
+    fn new(position: &Position, score: i32) -> Solution {
+        let cells = position.size().cells();
+        let played = position.moves_played();
+        if score == 0 {
+            return Solution {
+                score,
+                plies: cells - played,
+            };
+        }
+
+        // Recover the number of the winning stone from the score. Two stone
+        // numbers map to each score; the winner's parity picks one.
+        let s = score.unsigned_abs() as usize;
+        let winner_to_move = score > 0;
+        let stone = if ((cells + 2 - 2 * s - played) % 2 == 1) == winner_to_move {
+            cells + 2 - 2 * s
+        } else {
+            cells + 1 - 2 * s
+        };
+        Solution {
+            score,
+            plies: stone - played,
+        }
+    }
+
+    pub fn score(&self) -> i32 {
+        self.score
+    }
+
+    pub fn value(&self) -> Value {
+        match self.score {
+            0 => Value::Draw,
+            score if score > 0 => Value::Win,
+            _ => Value::Loss,
+        }
+    }
+
+    /// Moves left until the game ends under perfect play, counting both
+    /// sides. For a draw this is every empty cell.
+    pub fn plies(&self) -> usize {
+        self.plies
+    }
+}
+
+// The clock is only read every this many nodes.
+const TIME_CHECK_INTERVAL: u64 = 4096;
+
+/// When a solve gives up.
+struct Limit<'a> {
+    handle: &'a SearchHandle,
+    deadline: Option<Instant>,
+    stopped: Cell<bool>,
+}
+
+impl Limit<'_> {
+    /// Counts the `nodes`th node and returns whether to give up.
+    fn visit(&self, nodes: u64) -> bool {
+        if !self.stopped.get() {
+            let out_of_time = nodes.is_multiple_of(TIME_CHECK_INTERVAL)
+                && self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline);
+            self.stopped.set(out_of_time || self.handle.is_stopped());
+        }
+        self.stopped.get()
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.get()
+    }
+}
+
+/// Perfect-play solver. Its table holds exact scores, so it must not be
+/// shared with `bot::find_best_move_with_table`.
+pub struct Solver {
+    table: TranspositionTable,
+    nodes: AtomicU64,
+}
+
+impl Solver {
+    pub fn new(table: TranspositionTable) -> Solver {
+        Solver {
+            table,
+            nodes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn table(&self) -> &TranspositionTable {
+        &self.table
+    }
+
+    /// Positions searched since the solver was created or reset.
+    pub fn nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    /// Forgets every stored result, so the next solve starts cold.
+    pub fn reset(&self) {
+        self.table.clear();
+        self.nodes.store(0, Ordering::Relaxed);
+    }
+
+    /// Solves `position` exactly by narrowing the score with null-window
+    /// searches.
+    pub fn solve(&self, position: &Position) -> Result<Solution, EngineError> {
+        self.solve_with_handle(position, None, &SearchHandle::new())
+            .map(|solution| solution.expect("an unlimited solve always finishes"))
+    }
+
+    /// Like `solve`, but gives up and returns `None` once `handle` is
+    /// stopped or `time` has passed. Results found before then are kept in
+    /// the table, so solving again continues where this left off.
+    pub fn solve_with_handle(
+        &self,
+        position: &Position,
+        time: Option<Duration>,
+        handle: &SearchHandle,
+    ) -> Result<Option<Solution>, EngineError> {
+        let limit = Limit {
+            handle,
+            deadline: time.map(|time| Instant::now() + time),
+            stopped: Cell::new(false),
+        };
+        self.solve_within(position, &limit)
+    }
+
+    fn solve_within(
+        &self,
+        position: &Position,
+        limit: &Limit,
+    ) -> Result<Option<Solution>, EngineError> {
+        if position.is_game_over() {
+            return Err(EngineError::GameOver);
+        }
+
+        let mut position = position.clone();
+        let cells = position.size().cells() as i32;
+        let played = position.moves_played() as i32;
+        if position.winning_moves() > 0 {
+            let score = (cells + 1 - played) / 2;
+            return Ok(Some(Solution::new(&position, score)));
+        }
+
+        let mut min = -(cells - played) / 2;
+        let mut max = (cells + 1 - played) / 2;
+        while min < max {
+            // Probe near zero first: proving the sign is cheaper than
+            // proving an exact distance.
+            let mut med = min + (max - min) / 2;
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            } else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+            let score = self.negamax(&mut position, med, med + 1, limit);
+            if limit.is_stopped() {
+                return Ok(None);
+            }
+            if score <= med {
+                max = score;
+            } else {
+                min = score;
+            }
+        }
+        Ok(Some(Solution::new(&position, min)))
+    }
+
+    /// A column that keeps the solved score, so it wins as fast as
+    /// possible or loses as slowly as possible. Columns nearer the centre
+    /// are preferred among equals.
+    pub fn best_move(&self, position: &Position) -> Result<usize, EngineError> {
+        self.best_move_with_handle(position, None, &SearchHandle::new())
+            .map(|col| col.expect("an unlimited solve always finishes"))
+    }
+
+    /// Like `best_move`, but gives up and returns `None` once `handle` is
+    /// stopped or `time` has passed.
+    pub fn best_move_with_handle(
+        &self,
+        position: &Position,
+        time: Option<Duration>,
+        handle: &SearchHandle,
+    ) -> Result<Option<usize>, EngineError> {
+        let limit = Limit {
+            handle,
+            deadline: time.map(|time| Instant::now() + time),
+            stopped: Cell::new(false),
+        };
+        let Some(solution) = self.solve_within(position, &limit)? else {
+            return Ok(None);
+        };
+        let target = solution.score();
+        let cells = position.size().cells() as i32;
+        let played = position.moves_played() as i32;
+
+        let mut child = position.clone();
+        for col in position.size().centre_out() {
+            if !child.can_play(col) {
+                continue;
+            }
+            child.play_unchecked(col);
+            // Only a null window around the target is needed to tell
+            // whether this column reaches it.
+            let score = match child.outcome() {
+                Outcome::Won(_) => (cells + 1 - played) / 2,
+                Outcome::Draw => 0,
+                Outcome::InProgress if child.winning_moves() > 0 => -(cells - played) / 2,
+                Outcome::InProgress => -self.negamax(&mut child, -target, -target + 1, &limit),
+            };
+            child.undo();
+            if limit.is_stopped() {
+                return Ok(None);
+            }
+            if score >= target {
+                return Ok(Some(col));
+            }
+        }
+        unreachable!("no column reaches the solved score")
+    }
+
+    /// Scores `position`, which the side to move cannot win immediately,
+    /// within the window `alpha..beta`. Once `limit` stops the solve the
+    /// score is meaningless and nothing more is stored.
+    fn negamax(
+        &self,
+        position: &mut Position,
+        mut alpha: i32,
+        mut beta: i32,
+        limit: &Limit,
+    ) -> i32 {
+        let nodes = self.nodes.fetch_add(1, Ordering::Relaxed) + 1;
+        if limit.visit(nodes) {
+            return 0;
+        }
+        let rules = position.rules();
+        let size = rules.size();
+        let cells = size.cells() as i32;
+        let played = position.moves_played() as i32;
+        let mask = position.mask();
+
+        let candidates = position.non_losing_moves();
+        if candidates == 0 {
+            return -(cells - played) / 2;
+        }
+        if played >= cells - 2 {
+            return 0;
+        }
+
+        // The opponent cannot win on their next move, so the loss is at
+        // least two plies away.
+        let min = -(cells - 2 - played) / 2;
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+        let max = (cells - 1 - played) / 2;
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        let key = position.key();
+        let mut table_move = None;
+        if let Some(entry) = self.table.probe(key) {
+            table_move = entry.best_move;
+            let score = entry.score as i32;
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower => alpha = alpha.max(score),
+                Bound::Upper => beta = beta.min(score),
+            }
+            if alpha >= beta {
+                return score;
+            }
+        }
+
+        // Try the stored move first, then the moves that create the most
+        // new threats, breaking ties towards the centre.
+        let mut moves = [(0, 0); MAX_COLS];
+        let mut count = 0;
+        for col in size.centre_out() {
+            let move_bit = candidates & size.col_mask(col);
+            if move_bit == 0 {
+                continue;
+            }
+            let current = position.current() | move_bit;
+            let threats = rules.winning_cells(current) & !(mask | move_bit);
+            let priority = if Some(col) == table_move {
+                u32::MAX
+            } else {
+                threats.count_ones()
+            };
+            let mut index = count;
+            while index > 0 && moves[index - 1].0 < priority {
+                moves[index] = moves[index - 1];
+                index -= 1;
+            }
+            moves[index] = (priority, col);
+            count += 1;
+        }
+
+        let original_alpha = alpha;
+        let mut best_move = None;
+        for &(_, col) in &moves[..count] {
+            position.play_unchecked(col);
+            let score = -self.negamax(position, -beta, -alpha, limit);
+            position.undo();
+            if limit.is_stopped() {
+                return 0;
+            }
+
+            if score >= beta {
+                self.store(position, score, Bound::Lower, Some(col));
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+                best_move = Some(col);
+            }
+        }
+
+        let bound = if alpha > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.store(position, alpha, bound, best_move);
+        alpha
+    }
+
+    fn store(&self, position: &Position, score: i32, bound: Bound, best_move: Option<usize>) {
+        self.table.store(Entry {
+            key: position.key(),
+            depth: position.size().cells() - position.moves_played(),
+            score: score as f32,
+            bound,
+            best_move,
+        });
+    }
+}
+
+impl Default for Solver {
+    fn default() -> Solver {
+        Solver::new(TranspositionTable::default())
+    }
+}
+
+/// Solves `position` with a fresh table.
+pub fn solve(position: &Position) -> Result<Solution, EngineError> {
+    Solver::default().solve(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+    use std::collections::HashMap;
+
+    // Plain negamax over every position, memoised by key.
+    fn brute_force(position: &mut Position, memo: &mut HashMap<Bitboard, i32>) -> i32 {
+        if let Some(&score) = memo.get(&position.key()) {
+            return score;
+        }
+        let cells = position.size().cells() as i32;
+        let played = position.moves_played() as i32;
+        let mut best = None;
+        for col in 0..position.size().cols() {
+            if !position.can_play(col) {
+                continue;
+            }
+            position.play_unchecked(col);
+            let score = match position.outcome() {
+                Outcome::Won(_) => (cells + 1 - played) / 2,
+                Outcome::Draw => 0,
+                Outcome::InProgress => -brute_force(position, memo),
+            };
+            position.undo();
+            best = best.max(Some(score));
+        }
+        let score = best.unwrap();
+        memo.insert(position.key(), score);
+        score
+    }
+
+    #[test]
+    fn test_solve_endgames() {
+        for (moves, score) in [
+            ("2252576253462244111563365343671351441", -1),
+            ("7422341735647741166133573473242566", 1),
+            ("23163416124767223154467471272416755633", 0),
+        ] {
+            let position = parse_moves(Rules::STANDARD, moves).unwrap();
+            assert_eq!(solve(&position).unwrap().score(), score, "{}", moves);
+        }
+    }
+
+    #[test]
+    fn test_solve_matches_brute_force() {
+        let rules = Rules::new(BoardSize::new(5, 4).unwrap(), 3).unwrap();
+        let solver = Solver::default();
+        let mut memo = HashMap::new();
+        let mut positions = vec![Position::with_rules(rules)];
+        for _ in 0..3 {
+            let mut children = Vec::new();
+            for position in &positions {
+                let mut position = position.clone();
+                assert_eq!(
+                    solver.solve(&position).unwrap().score(),
+                    brute_force(&mut position, &mut memo),
+                    "{}",
+                    crate::notation::format_moves(&position)
+                );
+                for col in 0..rules.size().cols() {
+                    let mut child = position.clone();
+                    child.play_unchecked(col);
+                    if !child.is_game_over() {
+                        children.push(child);
+                    }
+                }
+            }
+            positions = children;
+        }
+    }
+
+    #[test]
+    fn test_solution_distance() {
+        let position = parse_moves(Rules::STANDARD, "121212").unwrap();
+        let solution = solve(&position).unwrap();
+        assert_eq!(solution.value(), Value::Win);
+        assert_eq!(solution.score(), 18);
+        assert_eq!(solution.plies(), 1);
+
+        // Second can only delay the loss by blocking once.
+        let position = parse_moves(Rules::STANDARD, "4455").unwrap();
+        let solution = solve(&position).unwrap();
+        assert_eq!(solution.value(), Value::Win);
+        assert_eq!(solution.plies(), 3);
+
+        let position = parse_moves(Rules::STANDARD, "44556").unwrap();
+        let solution = solve(&position).unwrap();
+        assert_eq!(solution.value(), Value::Loss);
+        assert_eq!(solution.plies(), 2);
+    }
+
+    #[test]
+    fn test_best_move() {
+        let solver = Solver::default();
+        let position = parse_moves(Rules::STANDARD, "4455").unwrap();
+        let best_move = solver.best_move(&position).unwrap();
+        assert!(best_move == 2 || best_move == 5);
+
+        let position = parse_moves(Rules::STANDARD, "121213").unwrap();
+        assert_eq!(solver.best_move(&position), Ok(0));
+
+        let mut over = Position::new();
+        for col in [0, 1, 0, 1, 0, 1, 0] {
+            over.play(col).unwrap();
+        }
+        assert_eq!(solver.best_move(&over), Err(EngineError::GameOver));
+    }
+
+    #[test]
+    fn test_solve_with_handle() {
+        let solver = Solver::default();
+        let position = parse_moves(Rules::STANDARD, "4444").unwrap();
+        let handle = SearchHandle::new();
+        assert_eq!(
+            solver.solve_with_handle(&position, Some(Duration::ZERO), &handle),
+            Ok(None)
+        );
+        handle.stop();
+        assert_eq!(
+            solver.best_move_with_handle(&position, None, &handle),
+            Ok(None)
+        );
+
+        // Nothing wrong was stored by the abandoned solves.
+        let position = parse_moves(Rules::STANDARD, "4455").unwrap();
+        let solution = solver
+            .solve_with_handle(
+                &position,
+                Some(Duration::from_secs(60)),
+                &SearchHandle::new(),
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(solution.plies(), 3);
+    }
+}
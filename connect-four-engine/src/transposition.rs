@@ -0,0 +1,202 @@
+use crate::bitboard::Bitboard;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const DEFAULT_TABLE_SIZE_MB: usize = 16;
+
+/// How a stored score relates to the true value of the position.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Entry {
+    pub key: Bitboard,
+    pub depth: usize,
+    pub score: f32,
+    pub bound: Bound,
+    pub best_move: Option<usize>,
+}
+
+/// What to do when a store lands on a slot that holds another position.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Replacement {
+    Always,
+    /// Keep the entry searched deeper, so shallow stores near the leaves
+    /// don't evict the expensive results near the root.
+    #[default]
+    DepthPreferred,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct TableStats {
+    pub probes: u64,
+    pub hits: u64,
+    pub stores: u64,
+    pub overwrites: u64,
+}
+
+impl TableStats {
+    pub fn hit_rate(&self) -> f64 {
+        if self.probes == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / self.probes as f64
+    }
+}
+
+/// Fixed-size hash table of search results keyed by `Position::key`. Each
+/// slot has its own lock so parallel searches can share one table.
+pub struct TranspositionTable {
+    slots: Vec<Mutex<Option<Entry>>>,
+    replacement: Replacement,
+    probes: AtomicU64,
+    hits: AtomicU64,
+    stores: AtomicU64,
+    overwrites: AtomicU64,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        TranspositionTable::with_replacement(size_mb, Replacement::default())
+    }
+
+    pub fn with_replacement(size_mb: usize, replacement: Replacement) -> TranspositionTable {
+        let slot_size = mem::size_of::<Mutex<Option<Entry>>>();
+        let len = (size_mb * 1024 * 1024 / slot_size).max(1);
+        TranspositionTable {
+            slots: (0..len).map(|_| Mutex::new(None)).collect(),
+            replacement,
+            probes: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            stores: AtomicU64::new(0),
+            overwrites: AtomicU64::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn replacement(&self) -> Replacement {
+        self.replacement
+    }
+
+    fn index(&self, key: Bitboard) -> usize {
+        let folded = (key as u64) ^ ((key >> 64) as u64);
+        let hash = folded.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        // The high bits of the product depend on every bit of the key, the
+        // low ones only on the key's low bits, so scale rather than take a
+        // remainder, which would keep just the low bits whenever the slot
+        // count is a power of two.
+        ((hash as u128 * self.slots.len() as u128) >> 64) as usize
+    }
+
+    pub fn probe(&self, key: Bitboard) -> Option<Entry> {
+        self.probes.fetch_add(1, Ordering::Relaxed);
+        let slot = self.slots[self.index(key)].lock().unwrap();
+        let entry = (*slot).filter(|entry| entry.key == key)?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry)
+    }
+
+    pub fn store(&self, entry: Entry) {
+        let mut slot = self.slots[self.index(entry.key)].lock().unwrap();
+        if let Some(existing) = *slot {
+            let keep = self.replacement == Replacement::DepthPreferred
+                && existing.key != entry.key
+                && existing.depth > entry.depth;
+            if keep {
+                return;
+            }
+            if existing.key != entry.key {
+                self.overwrites.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        *slot = Some(entry);
+        self.stores.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            *slot.lock().unwrap() = None;
+        }
+        self.probes.store(0, Ordering::Relaxed);
+        self.hits.store(0, Ordering::Relaxed);
+        self.stores.store(0, Ordering::Relaxed);
+        self.overwrites.store(0, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> TableStats {
+        TableStats {
+            probes: self.probes.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            stores: self.stores.load(Ordering::Relaxed),
+            overwrites: self.overwrites.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> TranspositionTable {
+        TranspositionTable::new(DEFAULT_TABLE_SIZE_MB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: Bitboard, depth: usize) -> Entry {
+        Entry {
+            key,
+            depth,
+            score: 1.0,
+            bound: Bound::Exact,
+            best_move: Some(3),
+        }
+    }
+
+    #[test]
+    fn test_probe_and_store() {
+        let table = TranspositionTable::new(1);
+        assert_eq!(table.probe(42), None);
+        table.store(entry(42, 4));
+        assert_eq!(table.probe(42), Some(entry(42, 4)));
+
+        let stats = table.stats();
+        assert_eq!(stats.probes, 2);
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.hit_rate(), 0.5);
+
+        table.clear();
+        assert_eq!(table.probe(42), None);
+    }
+
+    #[test]
+    fn test_replacement() {
+        let depth_preferred = TranspositionTable::new(0);
+        let always = TranspositionTable::with_replacement(0, Replacement::Always);
+        assert_eq!(depth_preferred.len(), 1);
+
+        for table in [&depth_preferred, &always] {
+            table.store(entry(1, 8));
+            table.store(entry(2, 2));
+        }
+        assert_eq!(depth_preferred.probe(1), Some(entry(1, 8)));
+        assert_eq!(always.probe(2), Some(entry(2, 2)));
+        assert_eq!(always.stats().overwrites, 1);
+
+        // The same position is always refreshed.
+        depth_preferred.store(entry(1, 3));
+        assert_eq!(depth_preferred.probe(1), Some(entry(1, 3)));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::agent::{Agent, GreedyAgent};
+use crate::bench::TestCase;
+use crate::bitboard::*;
+use crate::eval::EvalParams;
+use crate::notation::format_moves;
+use crate::rng::Rng;
+use crate::solver::Solver;
+use rayon::prelude::*;
+
+/// Fewest stones on the board in generated positions. Earlier positions
+/// can take the solver seconds each.
+pub const MIN_STONES: usize = 14;
+// Generated positions have up to this many stones past MIN_STONES.
+const STONE_SPREAD: usize = 16;
+
+/// Plays `count` games mixing random and greedy moves, stops each at a
+/// random point after `min_stones` stones and solves the position reached.
+/// Positions where the side to move can win or must block at once are
+/// skipped, since their static evaluation says little. Games are played
+/// and solved in parallel.
+pub fn generate_positions(count: usize, min_stones: usize, rng: &mut Rng) -> Vec<TestCase> {
+    let seeds: Vec<u64> = (0..count).map(|_| rng.next_u64()).collect();
+    seeds
+        .into_par_iter()
+        .map_init(Solver::default, |solver, seed| {
+            let mut rng = Rng::new(seed);
+            let position = quiet_position(min_stones, &mut rng);
+            solver.reset();
+            let score = solver.solve(&position).unwrap().score();
+            TestCase {
+                moves: format_moves(&position),
+                position,
+                score,
+            }
+        })
+        .collect()
+}
+
+fn quiet_position(min_stones: usize, rng: &mut Rng) -> Position {
+    let mut greedy = GreedyAgent::default();
+    loop {
+        let stones = min_stones + rng.below(STONE_SPREAD);
+        let mut position = Position::new();
+        while position.moves_played() < stones && !position.is_game_over() {
+            let col = if rng.below(3) == 0 {
+                let size = position.size();
+                rng.choose(&size.columns(size.playable(position.mask())))
+                    .unwrap()
+            } else {
+                greedy.choose_move(&position).unwrap()
+            };
+            position.play_unchecked(col);
+        }
+        let playable = position.size().playable(position.mask());
+        let side = position.side_to_move();
+        if !position.is_game_over()
+            && position.winning_moves() == 0
+            && position.threats(side.opponent()) & playable == 0
+        {
+            return position;
+        }
+    }
+}
+
+/// Settings of `tune`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TuneParams {
+    /// Gradient steps over the whole set of positions.
+    pub iterations: usize,
+    /// Adam step size, roughly the most a weight moves per step.
+    pub learning_rate: f64,
+}
+
+impl Default for TuneParams {
+    fn default() -> TuneParams {
+        TuneParams {
+            iterations: 1000,
+            learning_rate: 0.05,
+        }
+    }
+}
+
+/// Parameters found by `tune`, with the prediction error before and after.
+#[derive(Clone, PartialEq, Debug)]
+pub struct TuneResult {
+    pub params: EvalParams,
+    /// Scale turning evaluations into expected results, fitted to the
+    /// starting parameters.
+    pub scale: f64,
+    pub initial_error: f64,
+    pub error: f64,
+}
+
+/// Texel-style tuning: adjusts the weights of `start` so that the logistic
+/// of each evaluation predicts the solved result of its position, 1 for a
+/// win, 0.5 for a draw and 0 for a loss. The centre weights are extended
+/// to every distance from the middle so all of them are tuned. Weights of
+/// features that never occur in `cases`, such as immediate threats in
+/// positions from `generate_positions`, keep their starting value.
+/// `on_progress` receives each iteration's mean squared error.
+pub fn tune(
+    cases: &[TestCase],
+    start: &EvalParams,
+    params: &TuneParams,
+    mut on_progress: impl FnMut(usize, f64),
+) -> TuneResult {
+    let mut template = start.clone();
+    if let Some(case) = cases.first() {
+        let distances = case.position.size().cols().div_ceil(2);
+        if template.centre.len() < distances {
+            template.centre.resize(distances, 0.0);
+        }
+    }
+
+    // Evaluations are linear in the weights, so scoring each position once
+    // per weight, with only that weight set, gives features whose dot
+    // product with the weights is the evaluation.
+    let mut weights = to_weights(&template);
+    let samples: Vec<(Vec<f64>, f64)> = cases
+        .par_iter()
+        .map(|case| {
+            let features = (0..weights.len())
+                .map(|i| {
+                    let mut unit = vec![0.0; weights.len()];
+                    unit[i] = 1.0;
+                    from_weights(&template, &unit).evaluate(&case.position) as f64
+                })
+                .collect();
+            let result = match case.score {
+                score if score > 0 => 1.0,
+                0 => 0.5,
+                _ => 0.0,
+            };
+            (features, result)
+        })
+        .collect();
+
+    let scale = fit_scale(&samples, &weights);
+    let initial_error = error(&samples, &weights, scale);
+
+    // Adam keeps a running mean and variance of each weight's gradient,
+    // which copes with features on very different scales.
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+    let mut mean = vec![0.0; weights.len()];
+    let mut variance = vec![0.0; weights.len()];
+    let mut current_error = initial_error;
+    for iteration in 1..=params.iterations {
+        let gradient = gradient(&samples, &weights, scale);
+        for i in 0..weights.len() {
+            mean[i] = BETA1 * mean[i] + (1.0 - BETA1) * gradient[i];
+            variance[i] = BETA2 * variance[i] + (1.0 - BETA2) * gradient[i] * gradient[i];
+            let mean = mean[i] / (1.0 - BETA1.powi(iteration as i32));
+            let variance = variance[i] / (1.0 - BETA2.powi(iteration as i32));
+            weights[i] -= params.learning_rate * mean / (variance.sqrt() + 1e-8);
+        }
+        current_error = error(&samples, &weights, scale);
+        on_progress(iteration, current_error);
+    }
+
+    TuneResult {
+        params: from_weights(&template, &weights),
+        scale,
+        initial_error,
+        error: current_error,
+    }
+}
+
+fn to_weights(params: &EvalParams) -> Vec<f64> {
+    params
+        .centre
+        .iter()
+        .chain([
+            &params.connect_two,
+            &params.connect_three,
+            &params.open_two,
+            &params.open_three,
+            &params.immediate_threat,
+            &params.threat,
+            &params.parity_threat,
+        ])
+        .map(|&weight| weight as f64)
+        .collect()
+}
+
+fn from_weights(template: &EvalParams, weights: &[f64]) -> EvalParams {
+    let centre = template.centre.len();
+    let rest = &weights[centre..];
+    EvalParams {
+        centre: weights[..centre].iter().map(|&w| w as f32).collect(),
+        connect_two: rest[0] as f32,
+        connect_three: rest[1] as f32,
+        open_two: rest[2] as f32,
+        open_three: rest[3] as f32,
+        immediate_threat: rest[4] as f32,
+        threat: rest[5] as f32,
+        parity_threat: rest[6] as f32,
+    }
+}
+
+fn predict(features: &[f64], weights: &[f64], scale: f64) -> f64 {
+    let score: f64 = features.iter().zip(weights).map(|(f, w)| f * w).sum();
+    1.0 / (1.0 + (-scale * score).exp())
+}
+
+fn error(samples: &[(Vec<f64>, f64)], weights: &[f64], scale: f64) -> f64 {
+    let total: f64 = samples
+        .par_iter()
+        .map(|(features, result)| (result - predict(features, weights, scale)).powi(2))
+        .sum();
+    total / samples.len().max(1) as f64
+}
+
+fn gradient(samples: &[(Vec<f64>, f64)], weights: &[f64], scale: f64) -> Vec<f64> {
+    let mut gradient = samples
+        .par_iter()
+        .fold(
+            || vec![0.0; weights.len()],
+            |mut gradient, (features, result)| {
+                let p = predict(features, weights, scale);
+                let factor = 2.0 * (p - result) * p * (1.0 - p) * scale;
+                for (g, f) in gradient.iter_mut().zip(features) {
+                    *g += factor * f;
+                }
+                gradient
+            },
+        )
+        .reduce(
+            || vec![0.0; weights.len()],
+            |mut a, b| {
+                a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                a
+            },
+        );
+    let count = samples.len().max(1) as f64;
+    gradient.iter_mut().for_each(|g| *g /= count);
+    gradient
+}
+
+/// The logistic scale that best fits `weights`, by ternary search on its
+/// logarithm.
+fn fit_scale(samples: &[(Vec<f64>, f64)], weights: &[f64]) -> f64 {
+    let (mut low, mut high) = (1e-4f64.ln(), 10f64.ln());
+    for _ in 0..60 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if error(samples, weights, a.exp()) < error(samples, weights, b.exp()) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    ((low + high) / 2.0).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+
+    #[test]
+    fn test_features_reproduce_evaluation() {
+        let params = EvalParams {
+            centre: vec![0.5, 0.25, 0.125, 0.0],
+            ..EvalParams::default()
+        };
+        let position = parse_moves(Rules::STANDARD, "4453627").unwrap();
+        let weights = to_weights(&params);
+        let features: Vec<f64> = (0..weights.len())
+            .map(|i| {
+                let mut unit = vec![0.0; weights.len()];
+                unit[i] = 1.0;
+                from_weights(&params, &unit).evaluate(&position) as f64
+            })
+            .collect();
+        let score: f64 = features.iter().zip(&weights).map(|(f, w)| f * w).sum();
+        assert!((score - params.evaluate(&position) as f64).abs() < 1e-4);
+        assert_eq!(from_weights(&params, &weights), params);
+    }
+
+    #[test]
+    fn test_tune_reduces_error() {
+        let cases = generate_positions(24, 24, &mut Rng::new(1));
+        assert_eq!(cases.len(), 24);
+        for case in &cases {
+            assert!(case.position.moves_played() >= 24);
+            assert_eq!(format_moves(&case.position), case.moves);
+        }
+
+        let params = TuneParams {
+            iterations: 50,
+            ..TuneParams::default()
+        };
+        let mut errors = Vec::new();
+        let result = tune(&cases, &EvalParams::default(), &params, |_, error| {
+            errors.push(error)
+        });
+        assert_eq!(errors.len(), 50);
+        assert_eq!(result.params.centre.len(), 4);
+        assert!(result.error < result.initial_error);
+    }
+}
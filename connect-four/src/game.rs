@@ -1,29 +1,60 @@
 use crate::assets::Assets;
-use crate::game_difficulty::{GameDifficulty, GameDifficultyResource};
+use crate::game_difficulty::GameDifficultyResource;
 use crate::game_result::{GameResult, GameResultResource};
 use crate::settings::*;
 use crate::states::{AppState, GameState};
 use bevy::prelude::*;
+use bevy::tasks::futures::check_ready;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use connect_four_engine::agent::Agent;
 use connect_four_engine::bitboard::*;
-use connect_four_engine::bot::find_best_move;
+use connect_four_engine::bot::{SearchHandle, SearchResult};
+use connect_four_engine::error::EngineError;
+use connect_four_engine::notation::format_moves;
+use std::sync::{Arc, Mutex};
 
 #[derive(Resource)]
 struct GameData {
-    game_board: Bitboard,
-    player_board: Bitboard,
-    bot_board: Bitboard,
+    position: Position,
+    player: Player,
     player_col: usize,
     bot_col: usize,
     player_turn: bool,
 }
 
+/// The bot for this game, kept across moves so anything it learns about
+/// the game, such as its transposition table, carries over.
+#[derive(Resource)]
+struct Bot {
+    agent: Arc<Mutex<Box<dyn Agent>>>,
+}
+
+/// The bot's search, running on the async compute pool so the window keeps
+/// drawing while it thinks.
+#[derive(Resource)]
+pub struct BotSearch {
+    task: Task<Result<usize, EngineError>>,
+    handle: SearchHandle,
+    progress: Arc<Mutex<Option<SearchResult>>>,
+}
+
+impl BotSearch {
+    /// The last completed search iteration, if any.
+    pub fn progress(&self) -> Option<SearchResult> {
+        *self.progress.lock().unwrap()
+    }
+}
+
 #[derive(Component)]
 struct ActivePiece {
     col: usize,
 }
 
 #[derive(Component)]
-struct Piece {}
+struct Piece {
+    row: usize,
+    col: usize,
+}
 
 #[derive(Component)]
 struct Falling {
@@ -35,6 +66,7 @@ pub struct GamePlugin;
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(AppState::InGame), setup);
+        app.add_systems(OnExit(AppState::InGame), cancel_bot_search);
         app.add_systems(
             OnEnter(GameState::WhoTurn),
             check_who_turn.run_if(in_state(AppState::InGame)),
@@ -61,7 +93,13 @@ impl Plugin for GamePlugin {
         );
         app.add_systems(
             OnEnter(GameState::BotInput),
-            handle_bot_input.run_if(in_state(AppState::InGame)),
+            start_bot_search.run_if(in_state(AppState::InGame)),
+        );
+        app.add_systems(
+            Update,
+            handle_bot_input
+                .run_if(in_state(AppState::InGame))
+                .run_if(in_state(GameState::BotInput)),
         );
         app.add_systems(
             OnEnter(GameState::DropPiece),
@@ -83,6 +121,14 @@ impl Plugin for GamePlugin {
             OnEnter(GameState::IsGameOver),
             check_is_game_over.run_if(in_state(AppState::InGame)),
         );
+        app.add_systems(
+            OnEnter(GameState::GameOver),
+            log_moves.run_if(in_state(AppState::InGame)),
+        );
+        app.add_systems(
+            OnEnter(GameState::GameOver),
+            highlight_winning_line.run_if(in_state(AppState::InGame)),
+        );
         app.add_systems(
             OnEnter(GameState::NextTurn),
             next_turn.run_if(in_state(AppState::InGame)),
@@ -97,12 +143,15 @@ impl Plugin for GamePlugin {
 fn setup(
     mut commands: Commands,
     assets: Res<Assets>,
+    game_difficulty_resource: Res<GameDifficultyResource>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
+    commands.insert_resource(Bot {
+        agent: Arc::new(Mutex::new(game_difficulty_resource.game_difficulty.agent())),
+    });
     commands.insert_resource(GameData {
-        game_board: 0,
-        player_board: 0,
-        bot_board: 0,
+        position: Position::new(),
+        player: Player::First,
         player_col: 3,
         bot_col: 3,
         player_turn: true,
@@ -172,7 +221,7 @@ fn handle_player_drop_input(
 ) {
     if let Ok(active_piece) = query.single() {
         if keys.just_pressed(KeyCode::Space) {
-            if can_place(game_data.game_board, active_piece.col) {
+            if game_data.position.can_play(active_piece.col) {
                 game_data.player_col = active_piece.col;
                 next_state.set(GameState::DropPiece);
             }
@@ -186,31 +235,61 @@ fn hide_active_piece(mut query: Query<&mut Visibility, With<ActivePiece>>) {
     }
 }
 
+fn start_bot_search(mut commands: Commands, game_data: Res<GameData>, bot: Res<Bot>) {
+    let position = game_data.position.clone();
+    let agent = bot.agent.clone();
+    let handle = SearchHandle::new();
+    let progress = Arc::new(Mutex::new(None));
+
+    let task_handle = handle.clone();
+    let task_progress = progress.clone();
+    let task = AsyncComputeTaskPool::get().spawn(async move {
+        let mut agent = agent.lock().unwrap();
+        agent.choose_move_with_handle(&position, &task_handle, &mut |result| {
+            *task_progress.lock().unwrap() = Some(*result);
+        })
+    });
+    commands.insert_resource(BotSearch {
+        task,
+        handle,
+        progress,
+    });
+}
+
 fn handle_bot_input(
+    mut commands: Commands,
+    bot_search: Option<ResMut<BotSearch>>,
     mut game_data: ResMut<GameData>,
     mut game_result_resource: ResMut<GameResultResource>,
-    game_difficulty_resource: Res<GameDifficultyResource>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    let depth = match game_difficulty_resource.game_difficulty {
-        GameDifficulty::Easy => 4,
-        GameDifficulty::Normal => 8,
-        GameDifficulty::Hard => 12,
+    let Some(mut bot_search) = bot_search else {
+        return;
     };
-    let Some(best_move) = find_best_move(
-        game_data.game_board,
-        game_data.player_board,
-        game_data.bot_board,
-        depth,
-    ) else {
-        game_result_resource.game_result = GameResult::Unknow;
-        next_state.set(GameState::GameOver);
+    let Some(result) = check_ready(&mut bot_search.task) else {
         return;
     };
+    commands.remove_resource::<BotSearch>();
+
+    let best_move = match result {
+        Ok(best_move) => best_move,
+        Err(error) => {
+            game_result_resource.game_result = GameResult::Failed(error);
+            next_state.set(GameState::GameOver);
+            return;
+        }
+    };
     game_data.bot_col = best_move;
     next_state.set(GameState::DropPiece);
 }
 
+fn cancel_bot_search(mut commands: Commands, bot_search: Option<Res<BotSearch>>) {
+    if let Some(bot_search) = bot_search {
+        bot_search.handle.stop();
+        commands.remove_resource::<BotSearch>();
+    }
+}
+
 fn drop_piece(
     mut commands: Commands,
     mut game_data: ResMut<GameData>,
@@ -218,15 +297,17 @@ fn drop_piece(
     assets: Res<Assets>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    let next_row: Bitboard;
-    if game_data.player_turn {
-        next_row = get_next_row(game_data.game_board, game_data.player_col);
-        game_data.player_board ^= next_row;
+    let col = if game_data.player_turn {
+        game_data.player_col
     } else {
-        next_row = get_next_row(game_data.game_board, game_data.bot_col);
-        game_data.bot_board ^= next_row;
+        game_data.bot_col
+    };
+    let next_row = get_next_row(game_data.position.mask(), col);
+    if let Err(error) = game_data.position.play(col) {
+        game_result_resource.game_result = GameResult::Failed(error);
+        next_state.set(GameState::GameOver);
+        return;
     }
-    game_data.game_board |= next_row;
 
     let Some((row, col)) = indices_from_bitmask(next_row) else {
         game_result_resource.game_result = GameResult::Unknow;
@@ -240,7 +321,7 @@ fn drop_piece(
     let end_y = row as f32 * PIECE_SIZE - HALF_BOARD_HEIGHT + HALF_PIECE_SIZE;
 
     commands.spawn((
-        Piece {},
+        Piece { row, col },
         Falling {
             end_position: Vec3 {
                 x: end_x,
@@ -292,25 +373,33 @@ fn check_is_game_over(
     mut game_result_resource: ResMut<GameResultResource>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
-    if has_won(game_data.player_board) {
-        game_result_resource.game_result = GameResult::PlayerWon;
-        next_state.set(GameState::GameOver);
-        return;
-    }
+    game_result_resource.game_result = match game_data.position.outcome() {
+        Outcome::Won(winner) if winner == game_data.player => GameResult::PlayerWon,
+        Outcome::Won(_) => GameResult::BotWon,
+        Outcome::Draw => GameResult::Draw,
+        Outcome::InProgress => {
+            next_state.set(GameState::NextTurn);
+            return;
+        }
+    };
+    next_state.set(GameState::GameOver);
+}
 
-    if has_won(game_data.bot_board) {
-        game_result_resource.game_result = GameResult::BotWon;
-        next_state.set(GameState::GameOver);
+fn highlight_winning_line(game_data: Res<GameData>, query: Query<(&Piece, &mut Sprite)>) {
+    let winning_line = game_data.position.winning_line();
+    if winning_line == 0 {
         return;
     }
-
-    if is_board_full(game_data.game_board) {
-        game_result_resource.game_result = GameResult::Draw;
-        next_state.set(GameState::GameOver);
-        return;
+    let winning_cells = BoardSize::STANDARD.cells_from_bitmask(winning_line);
+    for (piece, mut sprite) in query {
+        if !winning_cells.contains(&(piece.row, piece.col)) {
+            sprite.color = Color::srgba(1.0, 1.0, 1.0, FADED_PIECE_ALPHA);
+        }
     }
+}
 
-    next_state.set(GameState::NextTurn);
+fn log_moves(game_data: Res<GameData>) {
+    info!("Moves: {}", format_moves(&game_data.position));
 }
 
 fn next_turn(mut game_data: ResMut<GameData>, mut next_state: ResMut<NextState<GameState>>) {
@@ -327,23 +416,29 @@ fn handle_replay(
     query: Query<Entity, With<Piece>>,
     game_data: Res<GameData>,
     game_result_resource: Res<GameResultResource>,
+    bot: Res<Bot>,
     mut next_state: ResMut<NextState<GameState>>,
 ) {
     for piece in query {
         commands.entity(piece).despawn();
     }
+    bot.agent.lock().unwrap().new_game();
 
     let player_turn = match game_result_resource.game_result {
         GameResult::PlayerWon => false,
         GameResult::BotWon => true,
         GameResult::Draw => game_data.player_turn,
+        GameResult::Failed(_) => true,
         GameResult::Unknow => true,
     };
 
     commands.insert_resource(GameData {
-        game_board: 0,
-        player_board: 0,
-        bot_board: 0,
+        position: Position::new(),
+        player: if player_turn {
+            Player::First
+        } else {
+            Player::Second
+        },
         player_col: 3,
         bot_col: 3,
         player_turn: player_turn,
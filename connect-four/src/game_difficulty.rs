@@ -1,10 +1,52 @@
+use crate::settings::EXPERT_THINK_TIME;
 use bevy::prelude::Resource;
+use connect_four_engine::agent::{
+    Agent, DEFAULT_SOLVE_FROM, DEFAULT_SOLVE_TIME, MinimaxAgent, SolverAgent,
+};
+use connect_four_engine::bot::SearchLimits;
+use connect_four_engine::eval::EvalParams;
+use std::time::Duration;
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum GameDifficulty {
     Easy,
     Normal,
     Hard,
+    Expert,
+    Perfect,
+}
+
+impl GameDifficulty {
+    /// Limits of the minimax search. Perfect searches like Expert until it
+    /// starts solving, and whenever a solve runs out of time.
+    pub fn search_limits(&self) -> SearchLimits {
+        match self {
+            GameDifficulty::Easy => SearchLimits::depth(4),
+            GameDifficulty::Normal => SearchLimits::depth(8),
+            GameDifficulty::Hard => SearchLimits::depth(12),
+            GameDifficulty::Expert | GameDifficulty::Perfect => {
+                SearchLimits::time(Duration::from_secs_f32(EXPERT_THINK_TIME))
+            }
+        }
+    }
+
+    /// The bot for this difficulty. Perfect solves once
+    /// `DEFAULT_SOLVE_FROM` stones are down, as the engine's `solver`
+    /// agent does; leaving the game stops it.
+    pub fn agent(&self) -> Box<dyn Agent> {
+        let minimax = Box::new(MinimaxAgent::new(
+            self.search_limits(),
+            EvalParams::default(),
+        ));
+        match self {
+            GameDifficulty::Perfect => Box::new(SolverAgent::with_opening(
+                minimax,
+                DEFAULT_SOLVE_FROM,
+                Some(DEFAULT_SOLVE_TIME),
+            )),
+            _ => minimax,
+        }
+    }
 }
 
 #[derive(Resource)]
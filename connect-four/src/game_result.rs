@@ -1,10 +1,12 @@
 use bevy::prelude::Resource;
+use connect_four_engine::error::EngineError;
 
 #[derive(PartialEq)]
 pub enum GameResult {
     PlayerWon,
     BotWon,
     Draw,
+    Failed(EngineError),
     Unknow,
 }
 
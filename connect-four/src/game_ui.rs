@@ -1,4 +1,5 @@
 use crate::assets::Assets;
+use crate::game::BotSearch;
 use crate::game_result::{GameResult, GameResultResource};
 use crate::states::{AppState, GameState};
 use crate::ui_settings::*;
@@ -10,6 +11,9 @@ struct GameOverUi {}
 #[derive(Component)]
 struct GameOverText {}
 
+#[derive(Component)]
+struct ThinkingText {}
+
 #[derive(Component)]
 struct BackButton {}
 
@@ -43,6 +47,20 @@ impl Plugin for GameUiPlugin {
             OnExit(GameState::GameOver),
             hide_game_over.run_if(in_state(AppState::InGame)),
         );
+        app.add_systems(
+            OnEnter(GameState::BotInput),
+            show_thinking.run_if(in_state(AppState::InGame)),
+        );
+        app.add_systems(
+            Update,
+            update_thinking_text
+                .run_if(in_state(AppState::InGame))
+                .run_if(in_state(GameState::BotInput)),
+        );
+        app.add_systems(
+            OnExit(GameState::BotInput),
+            hide_thinking.run_if(in_state(AppState::InGame)),
+        );
     }
 }
 
@@ -110,6 +128,32 @@ fn setup(mut commands: Commands, assets: Res<Assets>) {
                 });
         });
 
+    commands
+        .spawn((
+            Node {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Start,
+                justify_content: JustifyContent::End,
+                padding: UiRect::all(Val::Px(PADDING)),
+                ..default()
+            },
+            DespawnOnExit(AppState::InGame),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                ThinkingText {},
+                Text::new("Thinking..."),
+                TextColor(Color::BLACK),
+                TextFont {
+                    font: assets.font.clone(),
+                    font_size: SM_FONT_SIZE,
+                    ..default()
+                },
+                Visibility::Hidden,
+            ));
+        });
+
     commands
         .spawn((
             GameOverUi {},
@@ -212,11 +256,12 @@ fn update_game_over_text(
     game_result_resource: Res<GameResultResource>,
 ) {
     if let Ok((mut text, _game_over_text)) = query.single_mut() {
-        let game_over_text = match game_result_resource.game_result {
-            GameResult::PlayerWon => "Player won!",
-            GameResult::BotWon => "Bot won!",
-            GameResult::Draw => "Draw",
-            GameResult::Unknow => "Undefined",
+        let game_over_text = match &game_result_resource.game_result {
+            GameResult::PlayerWon => "Player won!".to_string(),
+            GameResult::BotWon => "Bot won!".to_string(),
+            GameResult::Draw => "Draw".to_string(),
+            GameResult::Failed(error) => format!("Error: {}", error),
+            GameResult::Unknow => "Undefined".to_string(),
         };
         *text = Text::new(game_over_text);
     }
@@ -234,6 +279,35 @@ fn hide_game_over(mut query: Query<(&mut Visibility, &GameOverUi)>) {
     }
 }
 
+fn show_thinking(mut query: Query<(&mut Visibility, &mut Text), With<ThinkingText>>) {
+    if let Ok((mut visibility, mut text)) = query.single_mut() {
+        *visibility = Visibility::Visible;
+        *text = Text::new("Thinking...");
+    }
+}
+
+fn update_thinking_text(
+    bot_search: Option<Res<BotSearch>>,
+    mut query: Query<&mut Text, With<ThinkingText>>,
+) {
+    let Some(progress) = bot_search.and_then(|bot_search| bot_search.progress()) else {
+        return;
+    };
+    if let Ok(mut text) = query.single_mut() {
+        let thinking_text = match progress.win_in() {
+            Some(moves) => format!("Thinking... (wins in {})", moves),
+            None => format!("Thinking... (depth {})", progress.stats.depth),
+        };
+        *text = Text::new(thinking_text);
+    }
+}
+
+fn hide_thinking(mut query: Query<&mut Visibility, With<ThinkingText>>) {
+    if let Ok(mut visibility) = query.single_mut() {
+        *visibility = Visibility::Hidden;
+    }
+}
+
 fn handle_back_button(
     query: Query<(&Interaction, &BackButton)>,
     mut next_state: ResMut<NextState<AppState>>,
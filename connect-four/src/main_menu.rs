@@ -139,6 +139,8 @@ fn get_difficulty_str(game_difficulty: &GameDifficulty) -> &str {
         GameDifficulty::Easy => "Easy",
         GameDifficulty::Normal => "Normal",
         GameDifficulty::Hard => "Hard",
+        GameDifficulty::Expert => "Expert",
+        GameDifficulty::Perfect => "Perfect",
     }
 }
 
@@ -165,6 +167,12 @@ fn next_difficulty(mut game_difficulty_resource: ResMut<GameDifficultyResource>)
             game_difficulty_resource.game_difficulty = GameDifficulty::Hard;
         }
         GameDifficulty::Hard => {
+            game_difficulty_resource.game_difficulty = GameDifficulty::Expert;
+        }
+        GameDifficulty::Expert => {
+            game_difficulty_resource.game_difficulty = GameDifficulty::Perfect;
+        }
+        GameDifficulty::Perfect => {
             game_difficulty_resource.game_difficulty = GameDifficulty::Easy;
         }
     }
@@ -5,3 +5,5 @@ pub const BOARD_HEIGHT: f32 = 576.0;
 pub const HALF_BOARD_WIDTH: f32 = BOARD_WIDTH / 2.0;
 pub const HALF_BOARD_HEIGHT: f32 = BOARD_HEIGHT / 2.0;
 pub const GRAVITY: f32 = 1200.0;
+pub const FADED_PIECE_ALPHA: f32 = 0.35;
+pub const EXPERT_THINK_TIME: f32 = 2.0;